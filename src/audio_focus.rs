@@ -0,0 +1,77 @@
+#[cfg(target_os = "linux")]
+use std::{thread, time::Duration};
+
+/// How often other apps' audio streams are polled
+#[cfg(target_os = "linux")]
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Watch on a background thread whether another app holds audio focus, calling `on_change(true)`
+/// when it takes focus (e.g. a call starts) and `on_change(false)` once it gives it back.
+/// On Linux a call is a PulseAudio/PipeWire stream with the `phone` media role, as used by
+/// softphones and browsers in calls. Other platforms don't report focus, nothing is watched there.
+#[cfg(target_os = "linux")]
+pub fn watch(on_change: impl Fn(bool) + Send + 'static) {
+    thread::spawn(move || {
+        let Some(mut lost) = phone_stream_active() else {
+            log::warn!("can't list audio streams with pactl, audio focus is not followed");
+            return;
+        };
+        if lost {
+            on_change(true);
+        }
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            // 偶尔查询失败（如音频服务重启）时保持原状态
+            let Some(current) = phone_stream_active() else {
+                continue;
+            };
+            if current != lost {
+                log::info!(
+                    "audio focus {}",
+                    if current {
+                        "lost to a call"
+                    } else {
+                        "regained"
+                    }
+                );
+                on_change(current);
+                lost = current;
+            }
+        }
+    });
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn watch(_on_change: impl Fn(bool) + Send + 'static) {
+    log::info!("audio focus events are not supported on this platform, focus_loss is ignored");
+}
+
+/// Whether any playing stream has the `phone` role, `None` if the streams can't be listed
+#[cfg(target_os = "linux")]
+fn phone_stream_active() -> Option<bool> {
+    let output = std::process::Command::new("pactl").args(["list", "sink-inputs"]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(has_phone_stream(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Whether a `pactl list sink-inputs` listing contains a stream with the `phone` role
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn has_phone_stream(listing: &str) -> bool {
+    listing.lines().any(|x| x.trim() == "media.role = \"phone\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phone_role_means_a_call() {
+        let music = "Sink Input #42\n\tCorked: no\n\tProperties:\n\t\tmedia.role = \"music\"\n\t\tapplication.name = \"Spotify\"\n";
+        let call = "Sink Input #43\n\tProperties:\n\t\tmedia.role = \"phone\"\n\t\tapplication.name = \"Zoom\"\n";
+        assert!(!has_phone_stream(""));
+        assert!(!has_phone_stream(music));
+        assert!(has_phone_stream(&format!("{}{}", music, call)));
+    }
+}
@@ -1,28 +1,252 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
 
-use crate::slint_types::{PlayMode, SortKey};
+use crate::{
+    dsp::EQ_BANDS,
+    slint_types::{ChannelMode, PlayMode, QueueEndBehavior, SongGrouping, SortKey, TriggerSource},
+    utils,
+};
+
+/// Which loudness normalization gain to apply when playing
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum ReplayGainMode {
+    Off,
+    Track,
+    Album,
+}
+
+/// Which songs random mode draws from
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum ShuffleScope {
+    /// Only the songs in the visible song list
+    Visible,
+    /// The whole library, regardless of the visible list
+    Library,
+}
+
+/// How a track change started by the user (next/prev/click) fades when crossfade is enabled
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum SkipFade {
+    /// Same crossfade as the natural end of a track
+    Crossfade,
+    /// A short crossfade of `short_fade_ms`
+    ShortFade,
+    /// Hard cut to the new track
+    Cut,
+}
+
+/// What to do while another app holds audio focus, e.g. during a call
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum FocusLossAction {
+    /// Keep playing as is
+    Off,
+    /// Pause, and resume once focus returns unless paused by hand in between
+    Pause,
+    /// Lower the volume until focus returns
+    Duck,
+}
+
+/// Get the executable's directory if running in portable mode,
+/// i.e. a `portable.txt` marker sits next to the executable or `--portable` is passed
+pub fn get_portable_dir() -> Option<&'static PathBuf> {
+    static PORTABLE_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+    PORTABLE_DIR
+        .get_or_init(|| {
+            let exe_dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+            let flag = std::env::args().any(|x| x == "--portable");
+            (flag || exe_dir.join("portable.txt").exists()).then_some(exe_dir)
+        })
+        .as_ref()
+}
+
+/// Get directory of the config file and the other saved state
+pub fn get_config_dir() -> PathBuf {
+    match get_portable_dir() {
+        Some(dir) => dir.clone(),
+        None => home::home_dir().expect("no home directory found").join(".config/zeedle"),
+    }
+}
 
 /// Get config file path
 fn get_cfg_path() -> PathBuf {
-    home::home_dir().expect("no home directory found").join(".config/zeedle/config.toml")
+    get_config_dir().join("config.toml")
+}
+
+/// Get directory where named playlists are saved
+pub fn get_playlist_dir() -> PathBuf {
+    get_config_dir().join("playlists")
+}
+
+/// Get path of per-album resume points file
+pub fn get_resume_path() -> PathBuf {
+    get_config_dir().join("album_resume.toml")
+}
+
+/// Get path of the play history file
+pub fn get_history_path() -> PathBuf {
+    get_config_dir().join("history.toml")
+}
+
+/// Get path of per-track overrides file
+pub fn get_overrides_path() -> PathBuf {
+    get_config_dir().join("overrides.toml")
+}
+
+/// Get cache directory (cover thumbnails, etc.)
+pub fn get_cache_dir() -> PathBuf {
+    match get_portable_dir() {
+        Some(dir) => dir.join("cache"),
+        None => home::home_dir().expect("no home directory found").join(".cache/zeedle"),
+    }
+}
+
+/// Get path of the scanned tags cache, which lives in the cache directory
+pub fn get_library_cache_path() -> PathBuf {
+    get_cache_dir().join("library.toml")
+}
+
+/// Get path of the SQLite library database, used instead of the tags cache file when enabled
+pub fn get_library_db_path() -> PathBuf {
+    get_cache_dir().join("library.db")
+}
+
+/// Load the TOML file at `path`, or return the default if it doesn't exist or is invalid.
+/// `what` names the file in log messages
+pub fn load_toml<T: serde::de::DeserializeOwned + Default>(path: &Path, what: &str) -> T {
+    match std::fs::read_to_string(path) {
+        Ok(content) => toml::from_str(&content).unwrap_or_else(|e| {
+            log::warn!("invalid {} file {:?}: {}", what, path, e);
+            T::default()
+        }),
+        Err(_) => T::default(),
+    }
+}
+
+/// Save `value` as TOML to `path`, creating its directory. Failures (e.g. a read-only portable drive)
+/// are only logged, `what` names the file in log messages
+pub fn save_toml<T: serde::Serialize>(value: &T, path: &Path, what: &str) {
+    if let Some(parent) = path.parent()
+        && let Err(e) = std::fs::create_dir_all(parent)
+    {
+        log::error!("failed to create {} directory {:?}: {}", what, parent, e);
+        return;
+    }
+    let content = toml::to_string_pretty(value)
+        .unwrap_or_else(|e| panic!("failed to serialize {}: {}", what, e));
+    // 先写临时文件再改名替换，写到一半被强制结束时原文件仍然完整
+    let tmp_path = path.with_extension("toml.tmp");
+    let result = std::fs::write(&tmp_path, content).and_then(|_| std::fs::rename(&tmp_path, path));
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&tmp_path);
+        log::error!("failed to write {} file {:?}: {}", what, path, e);
+    }
 }
 
 /// Used to save/recover ui state
-#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+/// Missing fields fall back to their defaults, so config files written by older versions still load
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
 pub struct Config {
+    /// Music directory of older versions, migrated into `song_dirs`. Still written for them as the first directory
     pub song_dir: PathBuf,
+    /// Directories (or M3U playlists) scanned into the library
+    pub song_dirs: Vec<PathBuf>,
+    /// Refresh the library when songs are added to or removed from the directories.
+    /// Some network drives don't report changes, turn it off there
+    pub watch_dirs: bool,
     pub current_song_path: Option<PathBuf>,
     pub progress: f32,
     pub play_mode: PlayMode,
+    #[serde(deserialize_with = "sort_key_or_default")]
     pub sort_key: SortKey,
     pub sort_ascending: bool,
     pub lang: String,
     pub light_ui: bool,
+    /// Max size (MB) of the album cover cache, applied to memory and disk separately
+    pub cover_cache_mb: u64,
+    /// Look up covers missing from the files on MusicBrainz and the Cover Art Archive
+    pub online_covers: bool,
+    pub radio: RadioWeights,
+    /// Most songs kept in the play history used by previous/next, the oldest are dropped first
+    pub history_size: usize,
+    pub replay_gain: ReplayGainMode,
+    /// Covers larger than this (px, longest edge) are downscaled before uploading as texture
+    pub max_texture_size: u32,
+    pub shuffle_scope: ShuffleScope,
+    pub queue_end_behavior: QueueEndBehavior,
+    /// Songs in the play queue, restored on the next launch
+    pub queue: Vec<PathBuf>,
+    /// How many of the queued songs were already played
+    pub queue_index: usize,
+    /// Show milliseconds in the time display
+    pub show_ms: bool,
+    /// Show the spectrum visualizer on the lyrics page
+    pub show_spectrum: bool,
+    /// Show the folder tree next to the song list
+    pub show_folders: bool,
+    /// Show the song list in album or artist groups
+    pub song_grouping: SongGrouping,
+    pub crossfade: Crossfade,
+    pub equalizer: Equalizer,
+    pub discord: DiscordPresence,
+    /// Volume ramp (ms) when pausing and resuming, 0 to pause instantly
+    pub pause_fade_ms: u64,
+    /// Seconds skipped by the left/right arrow keys
+    pub seek_step_secs: f32,
+    /// Name of the output device, empty for the system default
+    pub output_device: String,
+    /// Troubleshooting channel tool (swap, mono, single channel), `Stereo` leaves audio untouched
+    pub channel_mode: ChannelMode,
+    /// Left/right balance from -1 (left only) to 1 (right only), 0 is centered
+    pub balance: f32,
+    /// Master gain (dB) applied before normalization, independent of the volume
+    pub preamp_db: f32,
+    /// Output volume, linear from 0 to 2 (above 1 amplifies)
+    pub volume: f32,
+    /// Extensions scanned into the library, without the dot
+    pub formats: Vec<String>,
+    /// Files whose tags are read at the same time during scans, 0 for one per CPU core.
+    /// A low value avoids I/O storms on spinning disks
+    pub scan_threads: usize,
+    /// Physical window size of the last session, 0 if never saved
+    pub window_width: u32,
+    pub window_height: u32,
+    /// Physical window position of the last session
+    pub window_x: i32,
+    pub window_y: i32,
+    /// Start as the mini player. The window size above is still the one of the full UI
+    pub compact: bool,
+    /// Keep the window above other windows
+    pub always_on_top: bool,
+    /// Show an icon with playback controls in the system tray
+    pub tray_icon: bool,
+    /// Closing the window hides it to the tray instead of quitting, quit from the tray menu
+    pub close_to_tray: bool,
+    /// Pause when the output device in use is unplugged, instead of going on through the speakers
+    pub pause_on_unplug: bool,
+    /// Pause or duck while another app takes audio focus (a call). Only followed on Linux
+    pub focus_loss: FocusLossAction,
+    /// Show a desktop notification when a song starts, unless picked by hand in the focused window
+    pub notifications: bool,
+    /// Keep the scanned library in a SQLite database, shown right away on startup while the scan confirms it
+    pub library_db: bool,
+    /// Log level: off, error, warn, info, debug or trace. `RUST_LOG` overrides it
+    pub log_level: String,
+    /// The log file is rotated at startup once it exceeds this size (MB)
+    pub log_max_mb: u64,
+    /// How many rotated log files are kept
+    pub log_files: usize,
 }
 impl Default for Config {
     fn default() -> Self {
         Self {
             song_dir: home::home_dir().expect("no home directory found").join("Music"),
+            song_dirs: Vec::new(),
+            watch_dirs: true,
             current_song_path: None,
             progress: 0.0,
             play_mode: PlayMode::InOrder,
@@ -30,29 +254,218 @@ impl Default for Config {
             sort_ascending: true,
             lang: "".into(),
             light_ui: false,
+            cover_cache_mb: 128,
+            online_covers: false,
+            radio: RadioWeights::default(),
+            history_size: 200,
+            replay_gain: ReplayGainMode::Track,
+            max_texture_size: 4096,
+            shuffle_scope: ShuffleScope::Visible,
+            queue_end_behavior: QueueEndBehavior::Continue,
+            queue: Vec::new(),
+            queue_index: 0,
+            show_ms: false,
+            show_spectrum: false,
+            show_folders: false,
+            song_grouping: SongGrouping::None,
+            crossfade: Crossfade::default(),
+            equalizer: Equalizer::default(),
+            discord: DiscordPresence::default(),
+            pause_fade_ms: 150,
+            seek_step_secs: 5.0,
+            output_device: "".into(),
+            channel_mode: ChannelMode::Stereo,
+            balance: 0.0,
+            preamp_db: 0.0,
+            volume: 1.0,
+            formats: utils::AUDIO_EXTENSIONS.iter().map(|x| x.to_string()).collect(),
+            scan_threads: 0,
+            window_width: 0,
+            window_height: 0,
+            window_x: 0,
+            window_y: 0,
+            compact: false,
+            always_on_top: false,
+            tray_icon: true,
+            close_to_tray: false,
+            pause_on_unplug: true,
+            focus_loss: FocusLossAction::Off,
+            notifications: false,
+            library_db: false,
+            log_level: "info".into(),
+            log_max_mb: 10,
+            log_files: 3,
         }
     }
 }
 
+/// Problems found while parsing the config, `Config::load` hands them out since the logger
+/// isn't set up yet at that point
+static LOAD_WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Deserialize a sort key, an unknown one (e.g. hand edited) falls back to sorting by song name
+/// instead of discarding the whole config
+fn sort_key_or_default<'de, D: serde::Deserializer<'de>>(de: D) -> Result<SortKey, D::Error> {
+    use serde::Deserialize;
+    let value = toml::Value::deserialize(de)?;
+    Ok(value.try_into().unwrap_or_else(|e| {
+        LOAD_WARNINGS
+            .lock()
+            .unwrap()
+            .push(format!("invalid sort key in config, sorting by song name: {}", e));
+        SortKey::BySongName
+    }))
+}
+
+/// Weights used by artist radio to pick the next song, relative to the seed song
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct RadioWeights {
+    pub same_artist: f32,
+    pub same_genre: f32,
+    pub other: f32,
+    /// How many recently played songs are excluded from the candidates
+    pub recent_exclude: usize,
+}
+impl Default for RadioWeights {
+    fn default() -> Self {
+        Self {
+            same_artist: 8.0,
+            same_genre: 2.0,
+            other: 0.0,
+            recent_exclude: 20,
+        }
+    }
+}
+
+/// Crossfade between tracks, disabled when `secs` is 0
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Crossfade {
+    /// Crossfade length (seconds) at the natural end of a track
+    pub secs: f32,
+    /// Fade used when the user skips to another track
+    pub manual_skip: SkipFade,
+    pub short_fade_ms: u64,
+}
+impl Default for Crossfade {
+    fn default() -> Self {
+        Self {
+            secs: 0.0,
+            manual_skip: SkipFade::ShortFade,
+            short_fade_ms: 300,
+        }
+    }
+}
+
+impl Crossfade {
+    /// Fade length for a track change triggered by `trigger`, zero means a hard cut:
+    ///
+    /// | trigger                   | fade                                                    |
+    /// |---------------------------|---------------------------------------------------------|
+    /// | `Auto` (natural end)      | `secs`                                                  |
+    /// | `Next`, `Prev`, `ClickItem` | by `manual_skip`: `secs`, `short_fade_ms` or none     |
+    ///
+    /// Every trigger cuts when crossfade is disabled.
+    pub fn fade_for(&self, trigger: TriggerSource) -> Duration {
+        if self.secs <= 0. {
+            return Duration::ZERO;
+        }
+        let full = Duration::from_secs_f32(self.secs);
+        match (trigger, self.manual_skip) {
+            (TriggerSource::Auto, _) | (_, SkipFade::Crossfade) => full,
+            (_, SkipFade::ShortFade) => Duration::from_millis(self.short_fade_ms).min(full),
+            (_, SkipFade::Cut) => Duration::ZERO,
+        }
+    }
+}
+
+/// Show the playing song as Discord status, off by default
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct DiscordPresence {
+    pub enabled: bool,
+    /// Id of the application registered on the Discord developer portal, its name is shown as the player
+    pub app_id: String,
+}
+
+/// Graphic equalizer, one gain (dB) per band of `dsp::EQ_BANDS`
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Equalizer {
+    pub enabled: bool,
+    pub gains: Vec<f32>,
+}
+impl Default for Equalizer {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            gains: vec![0.0; EQ_BANDS.len()],
+        }
+    }
+}
+
+impl Equalizer {
+    /// Gain of every band, missing ones (e.g. hand edited config) are flat
+    pub fn band_gains(&self) -> [f32; EQ_BANDS.len()] {
+        std::array::from_fn(|band| self.gains.get(band).copied().unwrap_or(0.))
+    }
+}
+
 impl Config {
-    /// Load config from file, or return default if file not exists or invalid
-    pub fn load() -> Self {
+    /// Load config from file, or return default if file not exists or invalid.
+    /// Also return the problems found on the way, to be logged once the logger is set up
+    pub fn load() -> (Self, Vec<String>) {
         let cfg_path = get_cfg_path();
-        if cfg_path.exists() {
+        let mut cfg: Self = if cfg_path.exists() {
             let content = std::fs::read_to_string(&cfg_path).expect("failed to read config file");
-            toml::from_str(&content).unwrap_or_default()
+            toml::from_str(&content).unwrap_or_else(|e| {
+                LOAD_WARNINGS
+                    .lock()
+                    .unwrap()
+                    .push(format!("invalid config file {:?}, using defaults: {}", cfg_path, e));
+                Self::default()
+            })
         } else {
             Self::default()
+        };
+        // 旧版本只有一个音乐目录
+        if cfg.song_dirs.is_empty() {
+            cfg.song_dirs.push(cfg.song_dir.clone());
         }
+        let mut seen = HashSet::new();
+        cfg.song_dirs.retain(|x| seen.insert(x.clone()));
+        (cfg, std::mem::take(&mut *LOAD_WARNINGS.lock().unwrap()))
     }
 
-    /// Save config to file
+    /// Save config to file, a read-only location (e.g. a locked portable drive) only logs an error
     pub fn save(self) {
-        let cfg_path = get_cfg_path();
-        if let Some(parent) = cfg_path.parent() {
-            std::fs::create_dir_all(parent).expect("failed to create config directory");
-        }
-        let content = toml::to_string_pretty(&self).expect("failed to serialize config");
-        std::fs::write(cfg_path, content).expect("failed to write config file");
+        save_toml(&self, &get_cfg_path(), "config");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::PlayHistory;
+
+    #[test]
+    fn save_toml_replaces_file_without_leftovers() {
+        let dir =
+            std::env::temp_dir().join(format!("zeedle-test-save-toml-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("state.toml");
+        let mut state = PlayHistory {
+            songs: vec!["a.mp3".into()],
+        };
+        save_toml(&state, &path, "test state");
+        state.songs.push("b.mp3".into());
+        save_toml(&state, &path, "test state");
+        let loaded: PlayHistory = load_toml(&path, "test state");
+        let files = std::fs::read_dir(&dir).unwrap().count();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(loaded.songs, ["a.mp3", "b.mp3"]);
+        // 临时文件改名后不应留下
+        assert_eq!(files, 1);
     }
 }
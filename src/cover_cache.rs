@@ -0,0 +1,201 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::SystemTime,
+};
+
+use slint::{Rgba8Pixel, SharedPixelBuffer};
+
+use crate::utils;
+
+/// Decoded cover pixels, cheap to clone and safe to send across threads
+pub type CoverBuffer = SharedPixelBuffer<Rgba8Pixel>;
+
+/// Longest edge of the thumbnails written to the disk cache
+const THUMBNAIL_SIZE: u32 = 512;
+
+struct CoverEntry {
+    buffer: CoverBuffer,
+    last_access: u64,
+}
+
+/// Size-bounded LRU cache for album covers.
+/// Decoded covers are kept in memory, and downscaled thumbnails are kept on disk,
+/// each tier is capped to `max_bytes` separately.
+pub struct CoverCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    used_bytes: u64,
+    tick: u64,
+    entries: HashMap<PathBuf, CoverEntry>,
+}
+
+impl CoverCache {
+    pub fn new(dir: impl AsRef<Path>, max_mb: u64) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            max_bytes: max_mb * 1024 * 1024,
+            used_bytes: 0,
+            tick: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Get cover of audio file `path`, looking up memory first, then disk, then the file itself
+    pub fn get(&mut self, path: impl AsRef<Path>) -> Option<CoverBuffer> {
+        let path = path.as_ref();
+        self.tick += 1;
+        if let Some(entry) = self.entries.get_mut(path) {
+            entry.last_access = self.tick;
+            return Some(entry.buffer.clone());
+        }
+        let thumb_path = self.thumbnail_path(path)?;
+        let buffer = match load_thumbnail(&thumb_path) {
+            Some(buffer) => buffer,
+            None => {
+                let (buffer, width, height) = utils::read_album_cover(path)?;
+                let (buffer, width, height) =
+                    utils::downscale_cover(buffer, width, height, THUMBNAIL_SIZE);
+                self.save_thumbnail(&thumb_path, &buffer, width, height);
+                utils::to_pixel_buffer(buffer, width, height)
+            }
+        };
+        self.insert(path, buffer.clone());
+        Some(buffer)
+    }
+
+    /// Keep `cover` found elsewhere (e.g. online) as the cover of audio file `path`,
+    /// so later `get` calls serve it from memory or the disk thumbnail. Return it downscaled
+    pub fn insert_found(
+        &mut self,
+        path: impl AsRef<Path>,
+        (buffer, width, height): (Vec<u8>, u32, u32),
+    ) -> CoverBuffer {
+        let path = path.as_ref();
+        self.tick += 1;
+        let (buffer, width, height) = utils::downscale_cover(buffer, width, height, THUMBNAIL_SIZE);
+        if let Some(thumb_path) = self.thumbnail_path(path) {
+            self.save_thumbnail(&thumb_path, &buffer, width, height);
+        }
+        let buffer = utils::to_pixel_buffer(buffer, width, height);
+        self.insert(path, buffer.clone());
+        buffer
+    }
+
+    /// Thumbnail file of audio file `path` in the disk cache, if `get` or `insert_found` has written one
+    pub fn thumbnail_file(&self, path: impl AsRef<Path>) -> Option<PathBuf> {
+        self.thumbnail_path(path.as_ref()).filter(|x| x.exists())
+    }
+
+    fn insert(&mut self, path: &Path, buffer: CoverBuffer) {
+        let size = buffer_size(&buffer);
+        if size > self.max_bytes {
+            return;
+        }
+        self.used_bytes += size;
+        self.entries.insert(
+            path.to_path_buf(),
+            CoverEntry {
+                buffer,
+                last_access: self.tick,
+            },
+        );
+        while self.used_bytes > self.max_bytes {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_access)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.used_bytes -= buffer_size(&entry.buffer);
+                log::debug!("cover evicted from memory: {:?}", oldest);
+            }
+        }
+    }
+
+    /// Thumbnail file name is derived from the audio path, its size and mtime,
+    /// so a retagged file never hits a stale thumbnail
+    fn thumbnail_path(&self, path: &Path) -> Option<PathBuf> {
+        let meta = fs::metadata(path).ok()?;
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut key = path.display().to_string().into_bytes();
+        key.extend_from_slice(&meta.len().to_le_bytes());
+        key.extend_from_slice(&mtime.to_le_bytes());
+        Some(self.dir.join(format!("{:016x}.png", fnv1a(&key))))
+    }
+
+    fn save_thumbnail(&self, thumb_path: &Path, buffer: &[u8], width: u32, height: u32) {
+        if let Err(e) = fs::create_dir_all(&self.dir) {
+            log::warn!("failed to create cover cache directory {:?}: {}", self.dir, e);
+            return;
+        }
+        match image::save_buffer(thumb_path, buffer, width, height, image::ColorType::Rgba8) {
+            Ok(_) => {
+                // 磁盘淘汰放在后台线程，避免阻塞播放
+                let dir = self.dir.clone();
+                let max_bytes = self.max_bytes;
+                thread::spawn(move || evict_disk(&dir, max_bytes));
+            }
+            Err(e) => log::warn!("failed to write cover thumbnail {:?}: {}", thumb_path, e),
+        }
+    }
+}
+
+fn buffer_size(buffer: &CoverBuffer) -> u64 {
+    buffer.width() as u64 * buffer.height() as u64 * 4
+}
+
+/// Load a cached thumbnail, refreshing its mtime which serves as the access time
+fn load_thumbnail(thumb_path: &Path) -> Option<CoverBuffer> {
+    let img = image::open(thumb_path).ok()?.into_rgba8();
+    if let Ok(file) = fs::File::options().write(true).open(thumb_path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+    let (width, height) = img.dimensions();
+    Some(utils::to_pixel_buffer(img.into_vec(), width, height))
+}
+
+/// Remove least recently used thumbnails until the directory fits in `max_bytes`
+fn evict_disk(dir: &Path, max_bytes: u64) {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut files = read_dir
+        .filter_map(|x| x.ok())
+        .filter_map(|entry| {
+            let meta = entry.metadata().ok()?;
+            meta.is_file().then(|| {
+                (entry.path(), meta.len(), meta.modified().unwrap_or(SystemTime::UNIX_EPOCH))
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut total = files.iter().map(|(_, len, _)| len).sum::<u64>();
+    files.sort_by_key(|(_, _, mtime)| *mtime);
+    for (path, len, _) in files {
+        if total <= max_bytes {
+            break;
+        }
+        match fs::remove_file(&path) {
+            Ok(_) => {
+                total -= len;
+                log::debug!("cover thumbnail evicted from disk: {:?}", path);
+            }
+            Err(e) => log::warn!("failed to evict cover thumbnail {:?}: {}", path, e),
+        }
+    }
+}
+
+/// FNV-1a hash, stable across runs and toolchains unlike `DefaultHasher`
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
@@ -0,0 +1,192 @@
+use std::{
+    cell::RefCell,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use discord_rich_presence::{
+    DiscordIpc, DiscordIpcClient,
+    activity::{Activity, ActivityType, StatusDisplayType, Timestamps},
+};
+
+use crate::{config::DiscordPresence, slint_types::SongInfo};
+
+/// How often to try connecting again while Discord isn't running
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// Progress drifting further than this from the published start time is a seek, published again
+const SEEK_THRESHOLD_SECS: f64 = 2.0;
+
+/// Activity sent to the worker thread, which owns the IPC connection
+enum Update {
+    Song {
+        title: String,
+        artist: String,
+        /// Unix time (ms) the song would have started at to reach the current progress
+        start_ms: i64,
+        end_ms: i64,
+    },
+    Clear,
+    Exit,
+}
+
+/// "Listening to" status shown on the user's Discord profile
+struct Presence {
+    tx: Sender<Update>,
+    worker: JoinHandle<()>,
+    song: Option<(String, String, f32)>,
+    paused: bool,
+    /// Unix time (ms) the current song would have started at, moved by pauses and seeks
+    start_ms: i64,
+}
+
+thread_local! {
+    // 与系统媒体控件一样只在 UI 线程中更新，IPC 通信在后台线程完成
+    static PRESENCE: RefCell<Option<Presence>> = const { RefCell::new(None) };
+}
+
+/// Start publishing the playing song to Discord if enabled in the config.
+/// Must be called on the UI thread, Discord not running only disables it until it starts.
+pub fn init(config: &DiscordPresence) {
+    if !config.enabled {
+        return;
+    }
+    if config.app_id.trim().is_empty() {
+        log::warn!("discord presence enabled without an application id, disabled");
+        return;
+    }
+    let (tx, rx) = mpsc::channel();
+    let app_id = config.app_id.trim().to_string();
+    let worker = std::thread::spawn(move || run(app_id, rx));
+    PRESENCE.with_borrow_mut(|presence| {
+        *presence = Some(Presence {
+            tx,
+            worker,
+            song: None,
+            paused: true,
+            start_ms: 0,
+        })
+    });
+    log::info!("discord presence enabled");
+}
+
+/// Publish the song that is now current, shown once it is playing
+pub fn set_song(song: &SongInfo, duration: f32) {
+    PRESENCE.with_borrow_mut(|presence| {
+        let Some(presence) = presence else {
+            return;
+        };
+        presence.song = Some((song.song_name.to_string(), song.singer.to_string(), duration));
+        presence.start_ms = unix_ms();
+        presence.publish();
+    });
+}
+
+/// Follow pauses and seeks, called periodically with the playback state
+pub fn sync(paused: bool, progress: f32) {
+    PRESENCE.with_borrow_mut(|presence| {
+        let Some(presence) = presence else {
+            return;
+        };
+        let start_ms = unix_ms() - (progress as f64 * 1000.) as i64;
+        let drift = (start_ms - presence.start_ms).abs() as f64 / 1000.;
+        if paused == presence.paused && (paused || drift < SEEK_THRESHOLD_SECS) {
+            return;
+        }
+        presence.paused = paused;
+        presence.start_ms = start_ms;
+        presence.publish();
+    });
+}
+
+/// Clear the status and close the connection, called before exiting
+pub fn shutdown() {
+    if let Some(presence) = PRESENCE.take() {
+        let _ = presence.tx.send(Update::Exit);
+        if presence.worker.join().is_err() {
+            log::warn!("discord presence thread panicked");
+        }
+    }
+}
+
+impl Presence {
+    fn publish(&self) {
+        let update = match &self.song {
+            Some((title, artist, duration)) if !self.paused => Update::Song {
+                title: title.clone(),
+                artist: artist.clone(),
+                start_ms: self.start_ms,
+                end_ms: self.start_ms + (*duration as f64 * 1000.) as i64,
+            },
+            _ => Update::Clear,
+        };
+        let _ = self.tx.send(update);
+    }
+}
+
+/// Worker loop, only the latest update is kept while Discord can't be reached
+fn run(app_id: String, rx: Receiver<Update>) {
+    let mut client = DiscordIpcClient::new(&app_id);
+    let mut connected = false;
+    let mut last_attempt: Option<Instant> = None;
+    let mut pending = None;
+    loop {
+        match rx.recv_timeout(RETRY_INTERVAL) {
+            Ok(Update::Exit) | Err(RecvTimeoutError::Disconnected) => break,
+            Ok(update) => pending = Some(update),
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        if pending.is_none() {
+            continue;
+        }
+        if !connected && last_attempt.is_none_or(|x| x.elapsed() >= RETRY_INTERVAL) {
+            last_attempt = Some(Instant::now());
+            match client.connect() {
+                Ok(()) => {
+                    connected = true;
+                    log::info!("connected to discord");
+                }
+                Err(e) => log::debug!("discord not reachable: {}", e),
+            }
+        }
+        if !connected {
+            continue;
+        }
+        let result = match pending.as_ref() {
+            Some(Update::Song {
+                title,
+                artist,
+                start_ms,
+                end_ms,
+            }) => {
+                let mut activity = Activity::new()
+                    .activity_type(ActivityType::Listening)
+                    .status_display_type(StatusDisplayType::Details)
+                    .details(title.as_str())
+                    .timestamps(Timestamps::new().start(*start_ms).end(*end_ms));
+                if !artist.is_empty() {
+                    activity = activity.state(artist.as_str());
+                }
+                client.set_activity(activity)
+            }
+            _ => client.clear_activity(),
+        };
+        match result {
+            Ok(()) => pending = None,
+            Err(e) => {
+                // Discord 被关闭，稍后重连并重新发送
+                log::debug!("lost discord connection: {}", e);
+                let _ = client.close();
+                connected = false;
+            }
+        }
+    }
+    if connected {
+        let _ = client.clear_activity();
+        let _ = client.close();
+    }
+}
+
+fn unix_ms() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_millis() as i64)
+}
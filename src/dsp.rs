@@ -0,0 +1,525 @@
+use std::{
+    collections::VecDeque,
+    f32::consts::PI,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+
+use rodio::{ChannelCount, Sample, SampleRate, Source, source::SeekError};
+
+use crate::{config::Equalizer, slint_types::ChannelMode};
+
+/// Center frequencies (Hz) of the equalizer bands
+pub const EQ_BANDS: [f32; 10] = [31., 62., 125., 250., 500., 1000., 2000., 4000., 8000., 16000.];
+
+/// Largest boost or cut (dB) of an equalizer band
+pub const EQ_MAX_DB: f32 = 12.;
+
+/// Band gains (dB) of the built-in presets, in the order shown in the settings: Flat, Rock, Jazz, Vocal
+pub const EQ_PRESETS: [[f32; EQ_BANDS.len()]; 4] = [
+    [0.; EQ_BANDS.len()],
+    [5., 4., 3., 1., -1., -1., 1., 3., 4., 5.],
+    [3., 2., 1., 2., -1., -1., 0., 1., 2., 3.],
+    [-2., -2., -1., 1., 3., 4., 3., 1., 0., -1.],
+];
+
+/// Bandwidth of every equalizer band, about one octave
+const EQ_Q: f32 = 1.41;
+
+/// Live-adjustable DSP settings shared by the UI and every source being played,
+/// changes apply to the current track immediately without reloading it
+#[derive(Clone, Default)]
+pub struct DspControls {
+    channel_mode: Arc<AtomicU8>,
+    /// Left/right balance from -1 (left only) to 1 (right only), stored as `f32` bits
+    balance: Arc<AtomicU32>,
+    /// Preamp gain as a linear factor, stored as `f32` bits
+    preamp: Arc<AtomicU32>,
+    /// Whether played samples are collected for the spectrum visualizer
+    spectrum_enabled: Arc<AtomicBool>,
+    spectrum: Arc<Mutex<SpectrumSamples>>,
+    eq_enabled: Arc<AtomicBool>,
+    /// Gain (dB) of each equalizer band, stored as `f32` bits
+    eq_gains: Arc<[AtomicU32; EQ_BANDS.len()]>,
+    /// Bumped on every equalizer change, so sources know when to recompute their filters
+    eq_version: Arc<AtomicU32>,
+}
+
+/// Samples kept for the spectrum visualizer, the size of one FFT window
+pub const SPECTRUM_WINDOW: usize = 2048;
+
+/// Frames collected by a source before they are handed to the shared buffer, to keep locking rare
+const SPECTRUM_BATCH: usize = 512;
+
+/// Latest played samples, downmixed to mono
+#[derive(Default)]
+struct SpectrumSamples {
+    samples: VecDeque<Sample>,
+    sample_rate: SampleRate,
+}
+
+impl DspControls {
+    pub fn new(channel_mode: ChannelMode, balance: f32, preamp_db: f32, eq: &Equalizer) -> Self {
+        let controls = Self::default();
+        controls.set_channel_mode(channel_mode);
+        controls.set_balance(balance);
+        controls.set_preamp_db(preamp_db);
+        controls.set_eq_enabled(eq.enabled);
+        for (band, db) in eq.band_gains().into_iter().enumerate() {
+            controls.set_eq_band(band, db);
+        }
+        controls
+    }
+
+    pub fn set_preamp_db(&self, db: f32) {
+        let factor = 10f32.powf(db / 20.);
+        self.preamp.store(factor.to_bits(), Ordering::Relaxed);
+    }
+
+    fn preamp(&self) -> f32 {
+        f32::from_bits(self.preamp.load(Ordering::Relaxed))
+    }
+
+    pub fn set_channel_mode(&self, mode: ChannelMode) {
+        let value = match mode {
+            ChannelMode::Stereo => 0,
+            ChannelMode::Mono => 1,
+            ChannelMode::Swap => 2,
+            ChannelMode::LeftOnly => 3,
+            ChannelMode::RightOnly => 4,
+        };
+        self.channel_mode.store(value, Ordering::Relaxed);
+    }
+
+    pub fn set_balance(&self, balance: f32) {
+        self.balance.store(balance.clamp(-1., 1.).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Gain of the left and right channel for the current balance, the centered side stays at full level
+    fn balance_gains(&self) -> [Sample; 2] {
+        let balance = f32::from_bits(self.balance.load(Ordering::Relaxed));
+        [(1. - balance).min(1.), (1. + balance).min(1.)]
+    }
+
+    /// Start or stop collecting played samples for the spectrum visualizer
+    pub fn set_spectrum_enabled(&self, enabled: bool) {
+        self.spectrum_enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            self.spectrum.lock().unwrap().samples.clear();
+        }
+    }
+
+    fn spectrum_enabled(&self) -> bool {
+        self.spectrum_enabled.load(Ordering::Relaxed)
+    }
+
+    fn push_spectrum(&self, frames: &[Sample], sample_rate: SampleRate) {
+        let mut spectrum = self.spectrum.lock().unwrap();
+        spectrum.samples.extend(frames);
+        let excess = spectrum.samples.len().saturating_sub(SPECTRUM_WINDOW);
+        spectrum.samples.drain(..excess);
+        spectrum.sample_rate = sample_rate;
+    }
+
+    /// The last `SPECTRUM_WINDOW` mono samples played and their sample rate, if that many were collected
+    pub fn spectrum_window(&self) -> Option<(Vec<Sample>, SampleRate)> {
+        let spectrum = self.spectrum.lock().unwrap();
+        (spectrum.samples.len() == SPECTRUM_WINDOW)
+            .then(|| (spectrum.samples.iter().copied().collect(), spectrum.sample_rate))
+    }
+
+    pub fn set_eq_enabled(&self, enabled: bool) {
+        self.eq_enabled.store(enabled, Ordering::Relaxed);
+        self.eq_version.fetch_add(1, Ordering::Release);
+    }
+
+    /// Set the gain (dB) of equalizer `band`, clamped to `EQ_MAX_DB`. Unknown bands are ignored
+    pub fn set_eq_band(&self, band: usize, db: f32) {
+        let Some(gain) = self.eq_gains.get(band) else {
+            log::warn!("no equalizer band {}", band);
+            return;
+        };
+        gain.store(db.clamp(-EQ_MAX_DB, EQ_MAX_DB).to_bits(), Ordering::Relaxed);
+        self.eq_version.fetch_add(1, Ordering::Release);
+    }
+
+    fn eq_version(&self) -> u32 {
+        self.eq_version.load(Ordering::Acquire)
+    }
+
+    /// Gains (dB) of all bands, or `None` when the equalizer is off
+    fn eq_gains(&self) -> Option<[f32; EQ_BANDS.len()]> {
+        self.eq_enabled.load(Ordering::Relaxed).then(|| {
+            std::array::from_fn(|band| f32::from_bits(self.eq_gains[band].load(Ordering::Relaxed)))
+        })
+    }
+
+    fn channel_mode(&self) -> ChannelMode {
+        match self.channel_mode.load(Ordering::Relaxed) {
+            1 => ChannelMode::Mono,
+            2 => ChannelMode::Swap,
+            3 => ChannelMode::LeftOnly,
+            4 => ChannelMode::RightOnly,
+            _ => ChannelMode::Stereo,
+        }
+    }
+}
+
+/// Samples above this level are compressed by the soft limiter
+const LIMITER_THRESHOLD: Sample = 0.9;
+
+/// Wrap a decoded `source` with the whole playback chain: equalizer, preamp, normalization `gain`,
+/// the channel tools, then a soft limiter so boosted audio bends instead of clipping.
+/// The output is also tapped for the spectrum visualizer.
+pub fn apply_chain<S>(source: S, gain: f32, controls: &DspControls) -> impl Source + Send + 'static
+where
+    S: Source + Send + 'static,
+{
+    let equalizer = EqualizerSource {
+        input: source,
+        controls: controls.clone(),
+        version: None,
+        format: (0, 0),
+        filters: Vec::new(),
+        states: Vec::new(),
+        index: 0,
+    };
+    let preamp_controls = controls.clone();
+    let preamp = MapSamples {
+        input: equalizer,
+        f: move |x: Sample| x * preamp_controls.preamp(),
+    };
+    let channels = ChannelTools {
+        input: preamp.amplify(gain),
+        controls: controls.clone(),
+        frame: [0.; 2],
+        index: 0,
+    };
+    let limited = MapSamples {
+        input: channels,
+        f: soft_limit,
+    };
+    SpectrumTap {
+        input: limited,
+        controls: controls.clone(),
+        frame_sum: 0.,
+        index: 0,
+        frames: Vec::with_capacity(SPECTRUM_BATCH),
+    }
+}
+
+/// Pass samples below `LIMITER_THRESHOLD` through, and bend louder ones smoothly towards full scale
+fn soft_limit(x: Sample) -> Sample {
+    let level = x.abs();
+    if level <= LIMITER_THRESHOLD {
+        return x;
+    }
+    let headroom = 1. - LIMITER_THRESHOLD;
+    let limited = LIMITER_THRESHOLD + headroom * ((level - LIMITER_THRESHOLD) / headroom).tanh();
+    limited.copysign(x)
+}
+
+/// Source adapter applying `f` to every sample, used for the per-sample gain stages
+struct MapSamples<S, F> {
+    input: S,
+    f: F,
+}
+
+impl<S: Source, F: FnMut(Sample) -> Sample> Iterator for MapSamples<S, F> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        self.input.next().map(&mut self.f)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S: Source, F: FnMut(Sample) -> Sample> Source for MapSamples<S, F> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.input.current_span_len()
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)
+    }
+}
+
+/// Source adapter to swap, downmix or isolate the channels of a stereo source, then apply the balance.
+/// Sources with another channel count pass through untouched.
+struct ChannelTools<S> {
+    input: S,
+    controls: DspControls,
+    /// Current output frame, computed when its first sample is pulled
+    frame: [Sample; 2],
+    /// Index of the next sample of `frame` to output
+    index: usize,
+}
+
+impl<S: Source> Iterator for ChannelTools<S> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        if self.input.channels() != 2 && self.index == 0 {
+            return self.input.next();
+        }
+        if self.index == 0 {
+            let left = self.input.next()?;
+            let Some(right) = self.input.next() else {
+                return Some(left);
+            };
+            let [left, right] = match self.controls.channel_mode() {
+                ChannelMode::Stereo => [left, right],
+                ChannelMode::Mono => [(left + right) / 2.; 2],
+                ChannelMode::Swap => [right, left],
+                ChannelMode::LeftOnly => [left, 0.],
+                ChannelMode::RightOnly => [0., right],
+            };
+            let [left_gain, right_gain] = self.controls.balance_gains();
+            self.frame = [left * left_gain, right * right_gain];
+        }
+        let sample = self.frame[self.index];
+        self.index = (self.index + 1) % 2;
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S: Source> Source for ChannelTools<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.input.current_span_len()
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.index = 0;
+        self.input.try_seek(pos)
+    }
+}
+
+/// Source adapter passing samples through untouched,
+/// while copying them downmixed to mono into the spectrum buffer when the visualizer is on
+struct SpectrumTap<S> {
+    input: S,
+    controls: DspControls,
+    /// Sum of the samples of the current frame so far
+    frame_sum: Sample,
+    /// Index of the next sample within the current frame
+    index: ChannelCount,
+    /// Frames not yet handed to the shared buffer
+    frames: Vec<Sample>,
+}
+
+impl<S: Source> Iterator for SpectrumTap<S> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        let sample = self.input.next()?;
+        if self.controls.spectrum_enabled() {
+            let channels = self.input.channels().max(1);
+            self.frame_sum += sample;
+            self.index += 1;
+            if self.index >= channels {
+                self.frames.push(self.frame_sum / channels as Sample);
+                self.frame_sum = 0.;
+                self.index = 0;
+            }
+            if self.frames.len() >= SPECTRUM_BATCH {
+                self.controls.push_spectrum(&self.frames, self.input.sample_rate());
+                self.frames.clear();
+            }
+        }
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S: Source> Source for SpectrumTap<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.input.current_span_len()
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.frame_sum = 0.;
+        self.index = 0;
+        self.frames.clear();
+        self.input.try_seek(pos)
+    }
+}
+
+/// Coefficients of a biquad filter, normalized so that `a0` is 1
+#[derive(Clone, Copy)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    /// Peaking filter boosting or cutting `gain_db` around `freq`, from the RBJ audio EQ cookbook
+    fn peaking(freq: f32, gain_db: f32, sample_rate: SampleRate) -> Self {
+        let a = 10f32.powf(gain_db / 40.);
+        let w0 = 2. * PI * freq / sample_rate as f32;
+        let alpha = w0.sin() / (2. * EQ_Q);
+        let a0 = 1. + alpha / a;
+        Self {
+            b0: (1. + alpha * a) / a0,
+            b1: -2. * w0.cos() / a0,
+            b2: (1. - alpha * a) / a0,
+            a1: -2. * w0.cos() / a0,
+            a2: (1. - alpha / a) / a0,
+        }
+    }
+
+    /// Filter one sample, `state` holds the two delay values (transposed direct form II)
+    fn process(&self, x: Sample, state: &mut [Sample; 2]) -> Sample {
+        let y = self.b0 * x + state[0];
+        state[0] = self.b1 * x - self.a1 * y + state[1];
+        state[1] = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// Source adapter running every channel through the equalizer bands.
+/// Filters are recomputed in place when a band changes, so playback carries on without a gap.
+struct EqualizerSource<S> {
+    input: S,
+    controls: DspControls,
+    /// `DspControls::eq_version` the filters were computed for
+    version: Option<u32>,
+    /// Channel count and sample rate the filters were computed for
+    format: (ChannelCount, SampleRate),
+    /// One filter per band below the Nyquist frequency, empty when the equalizer is off
+    filters: Vec<Biquad>,
+    /// Delay values of every filter, per channel
+    states: Vec<[Sample; 2]>,
+    /// Channel of the next sample
+    index: ChannelCount,
+}
+
+impl<S: Source> EqualizerSource<S> {
+    /// Recompute the filters if the settings or the stream format changed since the last frame
+    fn update_filters(&mut self) {
+        let version = self.controls.eq_version();
+        let format = (self.input.channels().max(1), self.input.sample_rate());
+        if self.version == Some(version) && self.format == format {
+            return;
+        }
+        let (channels, sample_rate) = format;
+        self.filters = match self.controls.eq_gains() {
+            Some(gains) => EQ_BANDS
+                .iter()
+                .zip(gains)
+                .filter(|(freq, _)| **freq < sample_rate as f32 / 2.)
+                .map(|(freq, db)| Biquad::peaking(*freq, db, sample_rate))
+                .collect(),
+            None => Vec::new(),
+        };
+        // 只改变增益时保留滤波器状态，避免爆音
+        let len = self.filters.len() * channels as usize;
+        if self.format != format || self.states.len() != len {
+            self.states = vec![[0.; 2]; len];
+        }
+        self.version = Some(version);
+        self.format = format;
+    }
+}
+
+impl<S: Source> Iterator for EqualizerSource<S> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        if self.index == 0 {
+            self.update_filters();
+        }
+        let sample = self.input.next()?;
+        let channel = self.index as usize;
+        self.index = (self.index + 1) % self.format.0;
+        let states = self.states.chunks_exact_mut(self.filters.len().max(1)).nth(channel);
+        let Some(states) = states else {
+            return Some(sample);
+        };
+        Some(
+            self.filters
+                .iter()
+                .zip(states)
+                .fold(sample, |x, (filter, state)| filter.process(x, state)),
+        )
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<S: Source> Source for EqualizerSource<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.input.current_span_len()
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.index = 0;
+        self.states.fill([0.; 2]);
+        self.input.try_seek(pos)
+    }
+}
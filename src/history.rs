@@ -0,0 +1,29 @@
+use crate::{config, slint_types::SongInfo};
+
+/// Recently played songs behind previous/next, saved so "previous" still works after a restart.
+/// Kept apart from the config file like the other per-song state.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct PlayHistory {
+    /// Song paths (or URLs), oldest first
+    pub songs: Vec<String>,
+}
+
+impl PlayHistory {
+    /// Load play history from file, or return an empty one if file not exists or invalid
+    pub fn load() -> Self {
+        config::load_toml(&config::get_history_path(), "play history")
+    }
+
+    /// Save play history to file
+    pub fn save(&self) {
+        config::save_toml(self, &config::get_history_path(), "play history");
+    }
+
+    /// Play history of the songs in `history`, oldest first
+    pub fn from_songs(history: &[SongInfo]) -> Self {
+        Self {
+            songs: history.iter().map(|x| x.song_path.to_string()).collect(),
+        }
+    }
+}
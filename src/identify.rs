@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+
+use crate::{
+    online_cover,
+    slint_types::{SongInfo, TagSuggestion},
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+/// Recordings scored lower than this (out of 100) are too far off to suggest
+const MIN_MATCH_SCORE: u32 = 70;
+const MAX_SUGGESTIONS: usize = 5;
+
+#[derive(serde::Deserialize)]
+struct SearchResult {
+    #[serde(default)]
+    recordings: Vec<Recording>,
+}
+
+#[derive(serde::Deserialize)]
+struct Recording {
+    title: String,
+    #[serde(default)]
+    score: u32,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<ArtistCredit>,
+    #[serde(default)]
+    releases: Vec<Release>,
+}
+
+#[derive(serde::Deserialize)]
+struct ArtistCredit {
+    name: String,
+    #[serde(default)]
+    joinphrase: String,
+}
+
+#[derive(serde::Deserialize)]
+struct Release {
+    title: String,
+}
+
+/// Search MusicBrainz for recordings matching the tags of `song`, best first.
+/// Blocks on the network, call it off the UI thread. Nothing is written to the file.
+pub fn lookup(song: &SongInfo) -> Result<Vec<TagSuggestion>, String> {
+    let client = Client::builder()
+        .user_agent(online_cover::USER_AGENT)
+        .connect_timeout(REQUEST_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let (title, artist) = search_terms(song);
+    let mut query = format!("recording:\"{}\"", online_cover::escape_term(&title));
+    if let Some(artist) = artist {
+        query += &format!(" AND artist:\"{}\"", online_cover::escape_term(&artist));
+    }
+    log::info!("identifying <{}> with query: {}", song.song_path, query);
+    let response = client
+        .get("https://musicbrainz.org/ws/2/recording/")
+        .query(&[("query", query.as_str()), ("fmt", "json"), ("limit", "10")])
+        .send()
+        .and_then(|x| x.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let result: SearchResult = serde_json::from_reader(response).map_err(|e| e.to_string())?;
+    let mut suggestions = Vec::<TagSuggestion>::new();
+    for recording in result.recordings.into_iter().filter(|x| x.score >= MIN_MATCH_SCORE) {
+        let suggestion = TagSuggestion {
+            title: recording.title.into(),
+            artist: recording
+                .artist_credit
+                .iter()
+                .map(|x| format!("{}{}", x.name, x.joinphrase))
+                .collect::<String>()
+                .into(),
+            album: recording.releases.first().map(|x| x.title.as_str()).unwrap_or("").into(),
+            score: recording.score as i32,
+        };
+        // 同一首歌的多个录音版本标签往往完全相同，只保留一个
+        if !suggestions.iter().any(|x| {
+            (&x.title, &x.artist, &x.album)
+                == (&suggestion.title, &suggestion.artist, &suggestion.album)
+        }) {
+            suggestions.push(suggestion);
+        }
+    }
+    suggestions.truncate(MAX_SUGGESTIONS);
+    Ok(suggestions)
+}
+
+/// Title and artist to search for. Untagged files are named after the file,
+/// which is often `Artist - Title`
+fn search_terms(song: &SongInfo) -> (String, Option<String>) {
+    let title = song.song_name.trim();
+    let artist = song.singer.trim();
+    if !artist.is_empty() && artist != "unknown" {
+        return (title.to_string(), Some(artist.to_string()));
+    }
+    match title.split_once(" - ") {
+        Some((artist, title)) => (title.trim().to_string(), Some(artist.trim().to_string())),
+        None => (title.to_string(), None),
+    }
+}
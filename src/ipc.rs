@@ -0,0 +1,67 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// File holding the localhost port the running instance listens on.
+/// Like the single instance lock it is shared by every copy of the app, portable or not.
+fn port_file() -> PathBuf {
+    std::env::temp_dir().join("zeedle-instance.port")
+}
+
+/// Hand `path` (or just a request to show the window) to the running instance.
+/// Returns `false` if no instance is listening.
+pub fn forward_to_running(path: Option<&Path>) -> bool {
+    let Some(port) =
+        fs::read_to_string(port_file()).ok().and_then(|x| x.trim().parse::<u16>().ok())
+    else {
+        return false;
+    };
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let sent = TcpStream::connect_timeout(&addr, Duration::from_secs(1)).and_then(|mut stream| {
+        let line = path.map(|x| x.display().to_string()).unwrap_or_default();
+        writeln!(stream, "{}", line)
+    });
+    match sent {
+        Ok(_) => true,
+        Err(e) => {
+            log::warn!("failed to reach running instance on port {}: {}", port, e);
+            false
+        }
+    }
+}
+
+/// Listen for requests of later instances in a background thread.
+/// `on_open` receives the forwarded path, or `None` when the instance was started without a file.
+pub fn listen(on_open: impl Fn(Option<PathBuf>) + Send + 'static) {
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, 0)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::warn!("failed to listen for other instances: {}", e);
+            return;
+        }
+    };
+    let port = listener.local_addr().map(|x| x.port()).unwrap_or(0);
+    if let Err(e) = fs::write(port_file(), port.to_string()) {
+        log::warn!("failed to write instance port file: {}", e);
+        return;
+    }
+    log::info!("listening for other instances on port {}", port);
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(|x| x.ok()) {
+            // 只读取一行，防止异常连接阻塞监听线程
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+            let mut line = String::new();
+            if let Err(e) = BufReader::new(stream).read_line(&mut line) {
+                log::warn!("invalid request from another instance: {}", e);
+                continue;
+            }
+            let line = line.trim();
+            on_open((!line.is_empty()).then(|| PathBuf::from(line)));
+        }
+    });
+}
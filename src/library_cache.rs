@@ -0,0 +1,110 @@
+use std::{collections::BTreeMap, fs, path::Path, time::SystemTime};
+
+use crate::{config, library_db, slint_types::SongInfo, utils};
+
+/// Tag info of one audio file, valid as long as the file's mtime and size are unchanged
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct CachedSong {
+    /// Unix time (secs) the file was last modified
+    pub mtime: u64,
+    pub size: u64,
+    pub song_name: String,
+    pub singer: String,
+    pub duration_secs: f32,
+    pub genre: String,
+    pub album: String,
+    pub track_number: i32,
+    pub track_gain: f32,
+    pub album_gain: f32,
+    pub year: i32,
+    /// Unix time (secs) the file was added, see `SongInfo::date_added`
+    pub date_added: i32,
+}
+
+/// Tag info of scanned files keyed by path, so startup only re-reads files that changed since
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct LibraryCache {
+    pub songs: BTreeMap<String, CachedSong>,
+}
+
+impl CachedSong {
+    /// SongInfo of the file at `path` with these tags
+    pub fn to_song_info(&self, path: &str) -> SongInfo {
+        SongInfo {
+            id: 0,
+            song_path: path.into(),
+            song_name: self.song_name.as_str().into(),
+            singer: self.singer.as_str().into(),
+            duration: utils::format_duration(self.duration_secs),
+            duration_secs: self.duration_secs,
+            genre: self.genre.as_str().into(),
+            album: self.album.as_str().into(),
+            track_number: self.track_number,
+            track_gain: self.track_gain,
+            album_gain: self.album_gain,
+            skip_autoplay: false,
+            favorite: false,
+            year: self.year,
+            date_added: self.date_added,
+        }
+    }
+}
+
+/// mtime and size of `path`, used to tell if a cached entry is still valid
+pub fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    let mtime = utils::unix_secs(meta.modified().unwrap_or(SystemTime::UNIX_EPOCH));
+    Some((mtime, meta.len()))
+}
+
+impl LibraryCache {
+    /// Load cache from the library database if enabled, otherwise from file.
+    /// Return an empty one if file not exists or invalid
+    pub fn load() -> Self {
+        if let Some(cache) = library_db::load() {
+            return cache;
+        }
+        config::load_toml(&config::get_library_cache_path(), "library cache")
+    }
+
+    /// Save cache to the library database if enabled, otherwise to file
+    pub fn save(&self) {
+        if library_db::save(self) {
+            return;
+        }
+        config::save_toml(self, &config::get_library_cache_path(), "library cache");
+    }
+
+    /// Cached SongInfo of `path`, if the file still has the given mtime and size
+    pub fn get(&self, path: &str, (mtime, size): (u64, u64)) -> Option<SongInfo> {
+        let cached = self.songs.get(path).filter(|x| x.mtime == mtime && x.size == size)?;
+        Some(cached.to_song_info(path))
+    }
+
+    /// Remember tag info of `song` read from a file with the given mtime and size
+    pub fn insert(&mut self, song: &SongInfo, (mtime, size): (u64, u64)) {
+        self.songs.insert(
+            song.song_path.to_string(),
+            CachedSong {
+                mtime,
+                size,
+                song_name: song.song_name.to_string(),
+                singer: song.singer.to_string(),
+                duration_secs: song.duration_secs,
+                genre: song.genre.to_string(),
+                album: song.album.to_string(),
+                track_number: song.track_number,
+                track_gain: song.track_gain,
+                album_gain: song.album_gain,
+                year: song.year,
+                date_added: song.date_added,
+            },
+        );
+    }
+
+    /// Drop entries of files that are no longer part of the library
+    pub fn retain(&mut self, present: impl Fn(&str) -> bool) {
+        self.songs.retain(|path, _| present(path));
+    }
+}
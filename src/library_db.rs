@@ -0,0 +1,179 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
+
+use rusqlite::{Connection, Row, params};
+
+use crate::{
+    config,
+    library_cache::{CachedSong, LibraryCache},
+    slint_types::SongInfo,
+};
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS songs (
+    path TEXT PRIMARY KEY,
+    mtime INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    song_name TEXT NOT NULL,
+    singer TEXT NOT NULL,
+    duration_secs REAL NOT NULL,
+    genre TEXT NOT NULL,
+    album TEXT NOT NULL,
+    track_number INTEGER NOT NULL,
+    track_gain REAL NOT NULL,
+    album_gain REAL NOT NULL,
+    year INTEGER NOT NULL,
+    date_added INTEGER NOT NULL
+)";
+
+const COLUMNS: &str = "path, mtime, size, song_name, singer, duration_secs, genre, album, \
+    track_number, track_gain, album_gain, year, date_added";
+
+/// Library database, set once `enable` opened it
+static DB: OnceLock<Mutex<Connection>> = OnceLock::new();
+
+/// Keep scanned tags in a SQLite database instead of the plain cache file.
+/// If the database can't be opened, scans keep using the plain cache file.
+pub fn enable() {
+    if DB.get().is_some() {
+        return;
+    }
+    match open() {
+        Ok(conn) => {
+            let _ = DB.set(Mutex::new(conn));
+            log::info!("library database enabled");
+        }
+        Err(e) => log::warn!("library database unavailable, using the plain cache file: {}", e),
+    }
+}
+
+fn open() -> rusqlite::Result<Connection> {
+    let path = config::get_library_db_path();
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let conn = Connection::open(path)?;
+    // WAL 模式下写入不会阻塞读取
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.execute(SCHEMA, [])?;
+    Ok(conn)
+}
+
+/// All stored songs as a tags cache, `None` if the database is disabled
+pub fn load() -> Option<LibraryCache> {
+    let conn = DB.get()?.lock().unwrap();
+    let songs = read_all(&conn).unwrap_or_else(|e| {
+        log::error!("failed to read library database: {}", e);
+        BTreeMap::new()
+    });
+    Some(LibraryCache {
+        songs,
+    })
+}
+
+/// Make the database hold exactly the songs of `cache`, only rows that changed are written.
+/// Return false if the database is disabled
+pub fn save(cache: &LibraryCache) -> bool {
+    let Some(db) = DB.get() else {
+        return false;
+    };
+    if let Err(e) = write_changes(&mut db.lock().unwrap(), cache) {
+        log::error!("failed to update library database: {}", e);
+    }
+    true
+}
+
+/// Stored songs under any of `dirs`, as of the last scan. Empty if the database is disabled
+pub fn songs_in(dirs: &[PathBuf]) -> Vec<SongInfo> {
+    let Some(db) = DB.get() else {
+        return Vec::new();
+    };
+    let conn = db.lock().unwrap();
+    let sql = format!("SELECT {} FROM songs WHERE substr(path, 1, length(?1)) = ?1", COLUMNS);
+    let mut songs = Vec::new();
+    // 目录可能互相嵌套，同一首歌只取一次
+    let mut seen = HashSet::new();
+    for dir in dirs {
+        let prefix = dir.join("").display().to_string();
+        let result = conn.prepare_cached(&sql).and_then(|mut stmt| {
+            stmt.query_map([&prefix], read_row)?.collect::<rusqlite::Result<Vec<_>>>()
+        });
+        match result {
+            Ok(rows) => songs.extend(
+                rows.into_iter()
+                    .filter(|(path, _)| seen.insert(path.clone()))
+                    .map(|(path, song)| song.to_song_info(&path)),
+            ),
+            Err(e) => log::error!("failed to query library database for {:?}: {}", dir, e),
+        }
+    }
+    songs
+}
+
+fn read_all(conn: &Connection) -> rusqlite::Result<BTreeMap<String, CachedSong>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM songs", COLUMNS))?;
+    stmt.query_map([], read_row)?.collect()
+}
+
+fn read_row(row: &Row) -> rusqlite::Result<(String, CachedSong)> {
+    Ok((
+        row.get(0)?,
+        CachedSong {
+            mtime: row.get::<_, i64>(1)? as u64,
+            size: row.get::<_, i64>(2)? as u64,
+            song_name: row.get(3)?,
+            singer: row.get(4)?,
+            duration_secs: row.get(5)?,
+            genre: row.get(6)?,
+            album: row.get(7)?,
+            track_number: row.get(8)?,
+            track_gain: row.get(9)?,
+            album_gain: row.get(10)?,
+            year: row.get(11)?,
+            date_added: row.get(12)?,
+        },
+    ))
+}
+
+fn write_changes(conn: &mut Connection, cache: &LibraryCache) -> rusqlite::Result<()> {
+    let stored = read_all(conn)?;
+    let tx = conn.transaction()?;
+    let (mut removed, mut written) = (0, 0);
+    {
+        let mut delete = tx.prepare("DELETE FROM songs WHERE path = ?1")?;
+        for path in stored.keys().filter(|x| !cache.songs.contains_key(*x)) {
+            delete.execute([path])?;
+            removed += 1;
+        }
+        let mut upsert = tx.prepare(&format!(
+            "INSERT OR REPLACE INTO songs ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            COLUMNS
+        ))?;
+        for (path, song) in
+            cache.songs.iter().filter(|(path, song)| stored.get(*path) != Some(song))
+        {
+            upsert.execute(params![
+                path,
+                song.mtime as i64,
+                song.size as i64,
+                song.song_name,
+                song.singer,
+                song.duration_secs,
+                song.genre,
+                song.album,
+                song.track_number,
+                song.track_gain,
+                song.album_gain,
+                song.year,
+                song.date_added,
+            ])?;
+            written += 1;
+        }
+    }
+    tx.commit()?;
+    log::info!("library database updated: {} written, {} removed", written, removed);
+    Ok(())
+}
@@ -2,10 +2,78 @@ use std::{
     fs,
     io::{self, Write},
     path::{Path, PathBuf},
+    sync::OnceLock,
 };
 
 use env_logger::Target;
 use log::LevelFilter;
+use slint::{Model, SharedString};
+
+use crate::{
+    config,
+    slint_types::{PlayMode, UIState},
+};
+
+/// Log target of playback state transitions, filter on it to reconstruct a session
+pub const STATE_TARGET: &str = "zeedle::state";
+
+/// Snapshot of the playback state, logged around each player command
+#[derive(Clone, Debug)]
+pub struct StateSnapshot {
+    mode: PlayMode,
+    paused: bool,
+    history_index: i32,
+    history_len: usize,
+    song_id: i32,
+    song_path: SharedString,
+}
+
+impl StateSnapshot {
+    pub fn capture(ui_state: &UIState) -> Self {
+        let song = ui_state.get_current_song();
+        Self {
+            mode: ui_state.get_play_mode(),
+            paused: ui_state.get_paused(),
+            history_index: ui_state.get_history_index(),
+            history_len: ui_state.get_play_history().row_count(),
+            song_id: song.id,
+            song_path: song.song_path,
+        }
+    }
+}
+
+/// Log that player command `cmd` was received
+pub fn log_command(cmd: &str) {
+    log::debug!(target: STATE_TARGET, "[state] event=recv cmd={}", cmd);
+}
+
+/// Log which track player command `cmd` decided to play next, before the resulting `Play` applies
+pub fn log_decision(cmd: &str, detail: &str) {
+    log::debug!(target: STATE_TARGET, "[state] event=decide cmd={} detail={:?}", cmd, detail);
+}
+
+/// Log the state transition caused by player command `cmd`, in a `key=before->after` format.
+/// `detail` carries command specific info like the chosen track.
+pub fn log_transition(cmd: &str, before: &StateSnapshot, after: &StateSnapshot, detail: &str) {
+    log::debug!(
+        target: STATE_TARGET,
+        "[state] event=apply cmd={} mode={:?}->{:?} paused={}->{} history_index={}->{} history_len={}->{} song_id={}->{} song_path={:?}->{:?} detail={:?}",
+        cmd,
+        before.mode,
+        after.mode,
+        before.paused,
+        after.paused,
+        before.history_index,
+        after.history_index,
+        before.history_len,
+        after.history_len,
+        before.song_id,
+        after.song_id,
+        before.song_path.as_str(),
+        after.song_path.as_str(),
+        detail
+    );
+}
 
 struct MultiWriter {
     console: Box<dyn Write + Send>,
@@ -24,7 +92,18 @@ impl Write for MultiWriter {
     }
 }
 
+/// Log file in use, set by `init_default_logger`
+static LOG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Path of the log file in use, shown to users so they can attach it to bug reports
+pub fn log_path() -> PathBuf {
+    LOG_PATH.get().cloned().unwrap_or_else(get_log_path)
+}
+
 fn get_log_path() -> PathBuf {
+    if let Some(dir) = config::get_portable_dir() {
+        return dir.join("zeedle.log");
+    }
     let f_name = ".zeedle.log";
     if let Some(mut p) = home::home_dir() {
         p.push(f_name);
@@ -34,23 +113,56 @@ fn get_log_path() -> PathBuf {
     }
 }
 
-pub fn init_default_logger(path: Option<impl AsRef<Path>>) {
+/// Rotate `log_path` to `<log_path>.1`, shifting older logs up to `<log_path>.<keep>` and dropping the oldest
+fn rotate_logs(log_path: &Path, keep: usize) {
+    let rotated = |n: usize| {
+        let mut name = log_path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    let result = if keep == 0 {
+        fs::remove_file(log_path)
+    } else {
+        let _ = fs::remove_file(rotated(keep));
+        for n in (1..keep).rev() {
+            let _ = fs::rename(rotated(n), rotated(n + 1));
+        }
+        fs::rename(log_path, rotated(1))
+    };
+    if let Err(e) = result {
+        eprintln!("can't rotate log file {:?}: {}", log_path, e);
+    }
+}
+
+/// Log to console and the log file at `level`, `RUST_LOG` takes precedence when set.
+/// A log file larger than `max_bytes` is rotated at startup, keeping `keep` old files.
+pub fn init_default_logger(
+    path: Option<impl AsRef<Path>>,
+    level: LevelFilter,
+    max_bytes: u64,
+    keep: usize,
+) {
     let log_path = if let Some(p) = path {
         p.as_ref().to_path_buf()
     } else {
         get_log_path()
     };
-    if log_path.exists() && fs::metadata(&log_path).unwrap().len() > 1024 * 1024 * 10 {
-        fs::remove_file(&log_path).expect("Failed to remove old log file");
+    let _ = LOG_PATH.set(log_path.clone());
+    if fs::metadata(&log_path).is_ok_and(|x| x.len() > max_bytes) {
+        rotate_logs(&log_path, keep);
     }
-    let log_file = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&log_path)
-        .expect("can't open this file!");
+    // 日志文件不可写时（如只读的便携目录）只输出到控制台
+    let log_file: Box<dyn Write + Send> =
+        match fs::OpenOptions::new().create(true).append(true).open(&log_path) {
+            Ok(f) => Box::new(f),
+            Err(e) => {
+                eprintln!("can't open log file {:?}: {}, logging to console only", log_path, e);
+                Box::new(io::sink())
+            }
+        };
     let log_target = Box::new(MultiWriter {
         console: Box::new(io::stdout()),
-        file: Box::new(log_file),
+        file: log_file,
     });
     env_logger::builder()
         .format(move |buf, record| {
@@ -64,7 +176,8 @@ pub fn init_default_logger(path: Option<impl AsRef<Path>>) {
                 record.args()
             )
         })
-        .filter(None, LevelFilter::Info) // 设置日志级别为Info
+        .filter(None, level)
+        .parse_default_env() // 允许通过 RUST_LOG 覆盖，如 RUST_LOG=zeedle::state=debug
         .target(Target::Pipe(log_target))
         .init();
 }
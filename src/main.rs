@@ -1,35 +1,466 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 use std::{
-    cmp::Reverse,
-    path::PathBuf,
-    sync::{Arc, Mutex, mpsc},
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc,
+    },
     thread,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
-use rand::Rng;
-use rayon::slice::ParallelSliceMut;
-use rodio::{Decoder, Source, cpal};
-use slint::{Model, ToSharedString};
+use rodio::Source;
+use slint::{Model, SharedString, ToSharedString};
 mod slint_types;
 use slint_types::*;
+mod audio_focus;
 mod config;
-use config::Config;
+use config::{Config, FocusLossAction, ReplayGainMode, ShuffleScope};
+mod cover_cache;
+use cover_cache::{CoverBuffer, CoverCache};
+mod discord;
+mod dsp;
+use dsp::DspControls;
+mod history;
+use history::PlayHistory;
+mod identify;
+mod ipc;
+mod library_cache;
+mod library_db;
+use library_cache::LibraryCache;
 mod logger;
+use logger::StateSnapshot;
+mod media_keys;
+mod media_session;
+mod notification;
+mod online_cover;
+mod output;
+use output::DeviceSnapshot;
+mod overrides;
+use overrides::Overrides;
+mod resume;
+use resume::AlbumResume;
+mod playlist;
+mod shuffle;
+use shuffle::ShuffleBag;
+mod spectrum;
+use spectrum::{SPECTRUM_BARS, SpectrumAnalyzer};
+mod stream;
+mod tray;
 mod utils;
+mod watcher;
 
 /// Message in channel: ui --> backend
 /// Note: messages in the opposite direction (backend --> ui) are sent via slint::invoke_from_event_loop
 enum PlayerCommand {
-    Play(SongInfo, TriggerSource), // 从头播放某个音频文件
-    Pause,                         // 暂停/继续播放
-    ChangeProgress(f32),           // 拖拽进度条
-    PlayNext,                      // 播放下一首
-    PlayPrev,                      // 播放上一首
-    SwitchMode(PlayMode),          // 切换播放模式
-    RefreshSongList(PathBuf),      // 刷新歌曲列表
-    SortSongList(SortKey, bool),   // 刷新歌曲列表
-    SetLang(String),               // 设置语言
+    Play(SongInfo, TriggerSource),        // 从头播放某个音频文件
+    PlayUrl(String),                      // 播放 http(s) 地址上的音频，边下载边播放
+    Pause,                                // 暂停/继续播放
+    ChangeProgress(f32),                  // 拖拽进度条
+    SeekRelative(f32),                    // 相对当前位置快进/快退 (秒)
+    SetVolume(f32),                       // 设置音量 (0~2)
+    FadeVolume(f32),                      // 音量渐变到目标值，用于音频焦点变化时降低/恢复音量
+    SetCrossfade(f32),                    // 设置交叉淡入淡出时长 (秒，0 为关闭)
+    SetEqBand(usize, f32),                // 设置均衡器某个频段的增益 (dB)
+    SetEqEnabled(bool),                   // 开启/关闭均衡器
+    SetBalance(f32),                      // 设置左右声道平衡 (-1 为只有左声道，1 为只有右声道)
+    SetSleepTimer(Option<Duration>),      // 设置/取消睡眠定时，到时暂停播放
+    PlayNext(TriggerSource),              // 播放下一首 (Next 为用户跳过，Auto 为自然结束)
+    PlayPrev,                             // 播放上一首
+    SwitchMode(PlayMode),                 // 切换播放模式
+    RefreshSongList(Vec<PathBuf>, bool),  // 从所有音乐目录刷新歌曲列表 (是否强制完整扫描)
+    SortSongList(SortKey, bool),          // 刷新歌曲列表
+    SetLang(String),                      // 设置语言
+    StartRadio(SongInfo),                 // 以某首歌为种子开启电台模式
+    EnqueueNext(SongInfo),                // 插入到队列中下一首的位置
+    EnqueueLast(SongInfo),                // 添加到队列末尾
+    SavePlaylist(PathBuf, Vec<SongInfo>), // 保存歌曲为 M3U 歌单
+    ImportPlaylist(PathBuf),              // 导入其他播放器的歌单到队列
+    LoadPlaylist(PathBuf),                // 用已保存的歌单替换队列并开始播放
+    ToggleFavorite(SongInfo),             // 收藏/取消收藏某首歌
+    UpdateMetadata(SongInfo),             // 把编辑后的标题/歌手/专辑写回文件标签
+    OpenLog,                              // 用系统默认程序打开日志文件
+}
+
+impl PlayerCommand {
+    /// Short name used in state transition logs
+    fn name(&self) -> &'static str {
+        match self {
+            PlayerCommand::Play(..) => "Play",
+            PlayerCommand::PlayUrl(..) => "PlayUrl",
+            PlayerCommand::Pause => "Pause",
+            PlayerCommand::ChangeProgress(..) => "ChangeProgress",
+            PlayerCommand::SeekRelative(..) => "SeekRelative",
+            PlayerCommand::SetVolume(..) => "SetVolume",
+            PlayerCommand::FadeVolume(..) => "FadeVolume",
+            PlayerCommand::SetCrossfade(..) => "SetCrossfade",
+            PlayerCommand::SetEqBand(..) => "SetEqBand",
+            PlayerCommand::SetEqEnabled(..) => "SetEqEnabled",
+            PlayerCommand::SetBalance(..) => "SetBalance",
+            PlayerCommand::SetSleepTimer(..) => "SetSleepTimer",
+            PlayerCommand::PlayNext(..) => "PlayNext",
+            PlayerCommand::PlayPrev => "PlayPrev",
+            PlayerCommand::SwitchMode(..) => "SwitchMode",
+            PlayerCommand::RefreshSongList(..) => "RefreshSongList",
+            PlayerCommand::SortSongList(..) => "SortSongList",
+            PlayerCommand::SetLang(..) => "SetLang",
+            PlayerCommand::StartRadio(..) => "StartRadio",
+            PlayerCommand::EnqueueNext(..) => "EnqueueNext",
+            PlayerCommand::EnqueueLast(..) => "EnqueueLast",
+            PlayerCommand::SavePlaylist(..) => "SavePlaylist",
+            PlayerCommand::ImportPlaylist(..) => "ImportPlaylist",
+            PlayerCommand::LoadPlaylist(..) => "LoadPlaylist",
+            PlayerCommand::ToggleFavorite(..) => "ToggleFavorite",
+            PlayerCommand::UpdateMetadata(..) => "UpdateMetadata",
+            PlayerCommand::OpenLog => "OpenLog",
+        }
+    }
+
+    /// Command specific info for state transition logs, empty for most commands
+    fn detail(&self) -> String {
+        match self {
+            PlayerCommand::Play(_, trigger) => format!("trigger={:?}", trigger),
+            PlayerCommand::SortSongList(key, ascending) => {
+                format!("key={:?} ascending={}", key, ascending)
+            }
+            PlayerCommand::StartRadio(seed) => format!("seed={}", seed.song_path),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Logs the state transition of one player command: the state is captured on the UI thread when
+/// the command starts and again when the guard drops, so the command's own UI updates, queued in
+/// between, fall inside the two snapshots.
+struct TransitionLog {
+    ui_weak: slint::Weak<MainWindow>,
+    cmd: &'static str,
+    detail: String,
+    before: Arc<Mutex<Option<StateSnapshot>>>,
+}
+
+impl TransitionLog {
+    fn start(ui_weak: &slint::Weak<MainWindow>, cmd: &PlayerCommand) -> Self {
+        let before = Arc::new(Mutex::new(None));
+        let slot = before.clone();
+        with_state_snapshot(ui_weak, move |state| *slot.lock().unwrap() = Some(state));
+        Self {
+            ui_weak: ui_weak.clone(),
+            cmd: cmd.name(),
+            detail: cmd.detail(),
+            before,
+        }
+    }
+}
+
+impl Drop for TransitionLog {
+    fn drop(&mut self) {
+        let (cmd, detail, before) =
+            (self.cmd, std::mem::take(&mut self.detail), self.before.clone());
+        with_state_snapshot(&self.ui_weak, move |after| {
+            if let Some(before) = before.lock().unwrap().take() {
+                logger::log_transition(cmd, &before, &after, &detail);
+            }
+        });
+    }
+}
+
+/// Hand a snapshot of the playback state to `then` on the UI thread, after the UI updates queued
+/// so far. Nothing is captured once the event loop has quit.
+fn with_state_snapshot(
+    ui_weak: &slint::Weak<MainWindow>,
+    then: impl FnOnce(StateSnapshot) + Send + 'static,
+) {
+    let ui_weak = ui_weak.clone();
+    let _ = slint::invoke_from_event_loop(move || {
+        if let Some(ui) = ui_weak.upgrade() {
+            then(StateSnapshot::capture(&ui.global::<UIState>()));
+        }
+    });
+}
+
+/// Stop auto-skipping after this many unplayable songs in a row
+const MAX_SKIPS_ON_ERROR: usize = 10;
+
+/// Longest crossfade the settings allow, in seconds
+const MAX_CROSSFADE_SECS: f32 = 8.0;
+
+/// Volume kept while ducked for another app's audio focus, relative to the user volume
+const DUCK_FACTOR: f32 = 0.2;
+
+/// Length of the volume ramp when ducking for audio focus and back
+const FOCUS_FADE: Duration = Duration::from_millis(500);
+
+/// Refresh interval of the spectrum visualizer, about 30 fps
+const SPECTRUM_FRAME: Duration = Duration::from_millis(33);
+
+/// How much of the previous level a spectrum bar keeps each frame, so bars fall smoothly instead of flickering
+const SPECTRUM_FALLOFF: f32 = 0.85;
+
+/// How often the config is saved while running, in case the app doesn't exit cleanly
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Window size of the mini player
+const COMPACT_SIZE: slint::LogicalSize = slint::LogicalSize::new(360., 110.);
+
+/// Folder refreshes arriving within this window are merged, so only the last set of folders is scanned
+const REFRESH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Wait for more refresh requests following `cmd`, keeping only the last set of folders.
+/// Return the merged request, and the first other command that arrived meanwhile.
+fn debounce_refresh(
+    cmd: PlayerCommand,
+    rx: &mpsc::Receiver<PlayerCommand>,
+) -> (PlayerCommand, Option<PlayerCommand>) {
+    let PlayerCommand::RefreshSongList(mut dirs, mut full) = cmd else {
+        return (cmd, None);
+    };
+    loop {
+        match rx.recv_timeout(REFRESH_DEBOUNCE) {
+            Ok(PlayerCommand::RefreshSongList(next_dirs, next_full)) => {
+                log::info!("refresh of {:?} superseded by {:?}", dirs, next_dirs);
+                dirs = next_dirs;
+                full |= next_full;
+            }
+            Ok(other) => return (PlayerCommand::RefreshSongList(dirs, full), Some(other)),
+            Err(_) => return (PlayerCommand::RefreshSongList(dirs, full), None),
+        }
+    }
+}
+
+/// Apply `change` to every copy of the song at `path`: in the lists, the queue and the current song
+fn update_song(ui_state: &UIState, path: &str, change: impl Fn(&mut SongInfo)) {
+    for model in [ui_state.get_song_list(), ui_state.get_library(), ui_state.get_queue()] {
+        for (i, mut x) in model.iter().enumerate() {
+            if x.song_path == path {
+                change(&mut x);
+                model.set_row_data(i, x);
+            }
+        }
+    }
+    let mut cur_song = ui_state.get_current_song();
+    if cur_song.song_path == path {
+        change(&mut cur_song);
+        ui_state.set_current_song(cur_song);
+    }
+}
+
+/// Path of the named playlist `name` in the playlist directory, `None` if the name is not a valid file name
+fn named_playlist_path(name: &str) -> Option<PathBuf> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(['/', '\\']) {
+        log::warn!("invalid playlist name: <{}>", name);
+        return None;
+    }
+    Some(config::get_playlist_dir().join(format!("{}.m3u8", name)))
+}
+
+/// Show a status message in the control panel for a few seconds, `kind` picks its translated wording
+fn show_status(ui: &MainWindow, kind: StatusKind, message: SharedString) {
+    let ui_state = ui.global::<UIState>();
+    ui_state.set_status_kind(kind);
+    ui_state.set_status_message(message.clone());
+    let ui_weak = ui.as_weak();
+    slint::Timer::single_shot(Duration::from_secs(4), move || {
+        if let Some(ui) = ui_weak.upgrade() {
+            let ui_state = ui.global::<UIState>();
+            // 期间出现了新的提示时，由新提示的定时器负责清除
+            if ui_state.get_status_kind() == kind && ui_state.get_status_message() == message {
+                ui_state.set_status_message(SharedString::new());
+            }
+        }
+    });
+}
+
+/// Rebuild the visible song list from the library, applying the search query, the favorites filter and the current sort.
+/// While searching, songs are ranked by how well they match instead
+fn apply_song_filter(ui_state: &UIState) {
+    let favorites_only = ui_state.get_favorites_only();
+    let folder = ui_state.get_folder_filter();
+    let library = ui_state.get_library();
+    let library = library.iter().filter(|x| !favorites_only || x.favorite).filter(|x| {
+        folder.is_empty() || std::path::Path::new(x.song_path.as_str()).starts_with(folder.as_str())
+    });
+    let query = ui_state.get_filter_query();
+    let mut song_list = utils::filter_songs(library, &query, ui_state.get_search_lyrics());
+    if query.trim().is_empty() {
+        utils::sort_songs(&mut song_list, ui_state.get_sort_key(), ui_state.get_sort_ascending());
+    } else {
+        song_list.iter_mut().enumerate().for_each(|(idx, x)| x.id = idx as i32);
+    }
+    set_song_list(ui_state, song_list);
+}
+
+/// Show `song_list` as the visible songs, in album or artist groups when grouping is on.
+/// Grouped songs are reordered group by group, so playing next follows the list on screen
+fn set_song_list(ui_state: &UIState, mut song_list: Vec<SongInfo>) {
+    let groups = utils::group_songs(&mut song_list, ui_state.get_song_grouping());
+    ui_state.set_song_groups(groups.as_slice().into());
+    ui_state.set_song_list(song_list.as_slice().into());
+}
+
+/// Watch `dirs` for added or removed songs and refresh the library incrementally,
+/// replacing the watcher kept in `holder`
+fn watch_song_dirs(
+    ui: &MainWindow,
+    holder: &RefCell<Option<notify::RecommendedWatcher>>,
+    dirs: &[PathBuf],
+) {
+    let ui_weak = ui.as_weak();
+    holder.replace(watcher::watch_song_dirs(dirs, move || {
+        let ui_weak = ui_weak.clone();
+        // 退出时事件循环已结束，忽略最后一次通知
+        let _ = slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_weak.upgrade() {
+                log::info!("music directories changed, refreshing song list");
+                ui.invoke_refresh_song_list(false);
+            }
+        });
+    }));
+}
+
+/// Music directories shown in the settings
+fn song_dirs(ui_state: &UIState) -> Vec<PathBuf> {
+    ui_state.get_song_dirs().iter().map(|x| x.as_str().into()).collect()
+}
+
+/// `dirs` joined into one line for messages
+fn dir_names(dirs: &[PathBuf]) -> SharedString {
+    dirs.iter().map(|x| x.display().to_string()).collect::<Vec<_>>().join(", ").into()
+}
+
+/// `paths` as a model of strings for the UI
+fn path_list(paths: &[PathBuf]) -> slint::ModelRc<SharedString> {
+    paths.iter().map(|x| x.display().to_shared_string()).collect::<Vec<_>>().as_slice().into()
+}
+
+/// Append `song` to the play history, dropping the oldest entries beyond `limit`.
+/// `history_index` counts back from the newest entry, so it stays valid after trimming
+fn push_history(ui_state: &UIState, song: SongInfo, limit: usize) {
+    let mut history = ui_state.get_play_history().iter().collect::<Vec<_>>();
+    history.push(song);
+    let excess = history.len().saturating_sub(limit.max(1));
+    history.drain(..excess);
+    ui_state.set_play_history(history.as_slice().into());
+}
+
+/// Height of a lyric line
+const LYRIC_LINE_HEIGHT: f32 = 40.0;
+
+/// Height of a lyric line with its translation beneath
+const TRANSLATED_LYRIC_LINE_HEIGHT: f32 = 62.0;
+
+/// Show `lyrics` on the lyrics page, every line gets taller when some of them have translations
+fn set_lyrics(ui_state: &UIState, lyrics: &[utils::Lyric]) {
+    let translated = lyrics.iter().any(|x| !x.translation.is_empty());
+    ui_state.set_lyric_line_height(if translated {
+        TRANSLATED_LYRIC_LINE_HEIGHT
+    } else {
+        LYRIC_LINE_HEIGHT
+    });
+    ui_state.set_lyrics(lyrics.iter().map(LyricItem::from).collect::<Vec<_>>().as_slice().into());
+}
+
+/// Rebuild the folder tree from the library, dropping the folder filter if that folder is gone
+fn refresh_folder_tree(ui_state: &UIState) {
+    let folders = utils::folder_tree(&ui_state.get_library().iter().collect::<Vec<_>>());
+    let folder = ui_state.get_folder_filter();
+    if !folder.is_empty() && !folders.iter().any(|x| x.path == folder) {
+        ui_state.set_folder_filter(SharedString::new());
+    }
+    ui_state.set_folders(folders.as_slice().into());
+}
+
+/// Audio file passed on the command line, e.g. when opening a file associated with Zeedle
+fn cli_file() -> Option<PathBuf> {
+    let arg = std::env::args_os().skip(1).find(|x| !x.to_string_lossy().starts_with("--"))?;
+    // 不用 canonicalize：Windows 下会得到 \\?\ 前缀，与扫描得到的路径对不上
+    std::path::absolute(arg).ok()
+}
+
+/// Play audio file `path` opened from outside the app.
+/// If it is not in the library, the songs of its folder are added to the library for this session.
+fn open_external_file(ui: &MainWindow, path: &std::path::Path, overrides: &Overrides) {
+    let ui_state = ui.global::<UIState>();
+    let song_path = path.display().to_string();
+    let mut library = ui_state.get_library().iter().collect::<Vec<_>>();
+    if !library.iter().any(|x| x.song_path == song_path) {
+        let mut extra = path.parent().map(utils::read_folder_songs).unwrap_or_default();
+        extra.retain(|x| !library.iter().any(|y| y.song_path == x.song_path));
+        overrides.apply(&mut extra);
+        log::info!("add {} songs from the folder of <{}>", extra.len(), song_path);
+        library.extend(extra);
+        ui_state.set_library(library.as_slice().into());
+        refresh_folder_tree(&ui_state);
+        apply_song_filter(&ui_state);
+    }
+    match library.into_iter().find(|x| x.song_path == song_path) {
+        Some(song) => ui.invoke_play(song, TriggerSource::ClickItem),
+        None => log::warn!("<{}> is not a playable audio file", song_path),
+    }
+}
+
+/// Whether the window is shown and has keyboard focus
+fn window_focused(ui: &MainWindow) -> bool {
+    use slint::winit_030::WinitWindowAccessor;
+    ui.window().is_visible() && ui.window().with_winit_window(|w| w.has_focus()).unwrap_or(false)
+}
+
+/// Move the window to the saved position if a monitor still shows its top-left corner,
+/// otherwise (e.g. the monitor was unplugged) center it on the primary monitor
+fn restore_window_position(ui: &MainWindow, x: i32, y: i32) {
+    use slint::winit_030::WinitWindowAccessor;
+    let size = ui.window().size();
+    let position = ui.window().with_winit_window(|window| {
+        let on_screen = window.available_monitors().any(|m| {
+            let (pos, area) = (m.position(), m.size());
+            // 留出一些余量，保证标题栏可以拖动
+            (pos.x..pos.x + area.width as i32 - 50).contains(&x)
+                && (pos.y..pos.y + area.height as i32 - 50).contains(&y)
+        });
+        if on_screen {
+            return Some(slint::PhysicalPosition::new(x, y));
+        }
+        let monitor = window.primary_monitor().or_else(|| window.current_monitor())?;
+        let (pos, area) = (monitor.position(), monitor.size());
+        log::info!("saved window position ({}, {}) is off-screen, centering", x, y);
+        Some(slint::PhysicalPosition::new(
+            pos.x + (area.width as i32 - size.width as i32) / 2,
+            pos.y + (area.height as i32 - size.height as i32) / 2,
+        ))
+    });
+    if let Some(position) = position.flatten() {
+        ui.window().set_position(position);
+    }
+}
+
+/// Switch between the mini player and the full UI, resizing the window.
+/// The full UI only gets hidden, so switching back restores it as it was
+fn set_compact(ui: &MainWindow, compact: bool) {
+    let ui_state = ui.global::<UIState>();
+    if ui_state.get_compact() == compact {
+        return;
+    }
+    // 先改变模式再调整大小，否则会受到当前模式最小尺寸的限制
+    ui_state.set_compact(compact);
+    if compact {
+        let size = ui.window().size();
+        ui_state.set_expanded_width(size.width as i32);
+        ui_state.set_expanded_height(size.height as i32);
+        ui.window().set_size(COMPACT_SIZE);
+    } else if ui_state.get_expanded_width() > 0 && ui_state.get_expanded_height() > 0 {
+        ui.window().set_size(slint::PhysicalSize::new(
+            ui_state.get_expanded_width() as u32,
+            ui_state.get_expanded_height() as u32,
+        ));
+    } else {
+        ui.window().set_size(slint::LogicalSize::new(850., 500.));
+    }
+    log::info!("compact mode: {}", compact);
 }
 
 /// Set UI state to default (no song)
@@ -48,134 +479,925 @@ fn set_raw_ui_state(ui: &MainWindow) {
         song_name: "No song".into(),
         singer: "unknown".into(),
         duration: "00:00".into(),
+        duration_secs: 0.0,
+        genre: "unknown".into(),
+        album: "unknown".into(),
+        track_number: 0,
+        track_gain: 0.0,
+        album_gain: 0.0,
+        skip_autoplay: false,
+        favorite: false,
+        year: 0,
+        date_added: 0,
     });
     ui_state.set_lyrics(Vec::new().as_slice().into());
-    ui_state.set_song_list(Vec::new().as_slice().into());
-    ui_state.set_song_dir(
-        Config::default().song_dir.to_str().expect("failed to convert Path to String").into(),
-    );
+    set_song_list(&ui_state, Vec::new());
+    ui_state.set_library(Vec::new().as_slice().into());
+    ui_state.set_folders(Vec::new().as_slice().into());
+    ui_state.set_song_dirs(path_list(&[Config::default().song_dir]));
     ui_state.set_play_mode(PlayMode::InOrder);
     ui_state.set_paused(true);
     ui_state.set_dragging(false);
     ui_state.set_user_listening(false);
     ui_state.set_lyric_viewport_y(0.);
+    ui_state.set_current_lyric(-1);
 }
 
-/// Set UI state according to saved config
-fn set_start_ui_state(ui: &MainWindow, sink: &rodio::Sink) {
+/// Set UI state according to saved config.
+/// The library is scanned in the background by `spawn_library_scan` and the play history
+/// restored by `spawn_history_restore`, only the last song is restored here.
+fn set_start_ui_state(
+    ui: &MainWindow,
+    sink: &rodio::Sink,
+    cfg: &Config,
+    covers: &Arc<Mutex<CoverCache>>,
+    overrides: &Overrides,
+    dsp: &DspControls,
+) {
     let ui_state = ui.global::<UIState>();
-    let cfg = Config::load();
-    let song_list = utils::read_song_list(&cfg.song_dir, cfg.sort_key, cfg.sort_ascending);
-    if song_list.is_empty() {
-        log::warn!(
-            "song list is empty in directory: {:?}, using default UI state ...",
-            cfg.song_dir
-        );
-        set_raw_ui_state(ui);
-        return;
-    }
-    log::info!("loaded {} songs from directory: {:?}", song_list.len(), cfg.song_dir);
+    set_raw_ui_state(ui);
+    // 手动改坏的配置值回退到默认音量
+    let volume = if cfg.volume.is_finite() {
+        cfg.volume.clamp(0., 2.)
+    } else {
+        1.
+    };
+    sink.set_volume(volume);
+    ui_state.set_volume(volume);
     ui.invoke_set_light_theme(cfg.light_ui);
     ui_state.set_sort_key(cfg.sort_key);
     ui_state.set_sort_ascending(cfg.sort_ascending);
     ui_state.set_last_sort_key(cfg.sort_key);
-    ui_state.set_progress(cfg.progress);
-    ui_state.set_paused(true);
     ui_state.set_play_mode(cfg.play_mode);
+    ui_state.set_queue_end_behavior(cfg.queue_end_behavior);
+    ui_state.set_show_ms(cfg.show_ms);
+    ui_state.set_show_spectrum(cfg.show_spectrum);
+    ui_state.set_show_folders(cfg.show_folders);
+    ui_state.set_always_on_top(cfg.always_on_top);
+    ui_state.set_song_grouping(cfg.song_grouping);
     ui_state.set_lang(cfg.lang.clone().into());
     slint::select_bundled_translation(&cfg.lang)
         .unwrap_or_else(|_| panic!("failed to set language: {}", cfg.lang));
-    ui_state.set_song_list(song_list.as_slice().into());
-    ui_state.set_song_dir(cfg.song_dir.to_str().expect("failed to convert Path to String").into());
-    ui_state.set_about_info(utils::get_about_info());
-    let cur_song_info = utils::read_meta_info(
-        cfg.current_song_path.unwrap_or(song_list[0].song_path.as_str().into()),
-    )
-    .expect("failed to read meta info of current song");
-    let dura = cur_song_info
-        .clone()
-        .duration
-        .split(':')
-        .map(|x| x.parse::<f32>().unwrap_or(0.))
-        .rev()
-        .reduce(|acc, x| acc + x * 60.)
-        .unwrap_or(0.);
-    ui_state.set_duration(dura);
-    ui_state.set_current_song(cur_song_info.clone());
-    ui_state.set_lyrics(utils::read_lyrics(&cur_song_info.song_path).as_slice().into());
-    let cover = utils::read_album_cover(&cur_song_info.song_path);
+    ui_state.set_song_dirs(path_list(&cfg.song_dirs));
+    // 恢复上次的播放队列，已不存在的文件直接丢弃
+    let mut queue = Vec::new();
+    let mut queue_index = 0;
+    for (idx, path) in cfg.queue.iter().enumerate() {
+        match utils::read_meta_info(path) {
+            Some(song) => {
+                queue_index += (idx < cfg.queue_index) as i32;
+                queue.push(song);
+            }
+            None => log::warn!("queued song {:?} is gone, dropped from queue", path),
+        }
+    }
+    overrides.apply(&mut queue);
+    ui_state.set_queue(queue.as_slice().into());
+    ui_state.set_queue_index(queue_index);
+    // 上次播放的歌曲不依赖扫描结果，先行恢复，扫描期间就可以继续播放
+    // 恢复失败时当前歌曲保持为空，扫描完成后会载入列表中的第一首
+    let Some(cur_song_path) = cfg.current_song_path.as_ref().filter(|x| !x.as_os_str().is_empty())
+    else {
+        return;
+    };
+    let Some(mut cur_song_info) = utils::read_meta_info(cur_song_path) else {
+        log::warn!(
+            "last played song {:?} is missing or unreadable, skip restoring it",
+            cur_song_path
+        );
+        return;
+    };
+    overrides.apply(std::slice::from_mut(&mut cur_song_info));
+    let song_path = cur_song_info.song_path.clone();
+    if let Err(e) =
+        load_paused_song(ui, sink, cur_song_info, cfg.progress, covers, cfg.replay_gain, dsp)
+    {
+        log::warn!("failed to restore last song <{}>: {}", song_path, e);
+    }
+}
+
+/// Restore the saved play history on a worker thread, so "previous" works right after a restart.
+/// Tags come from the library cache where it is up to date, only the other files are read.
+/// Songs played before the restore finishes stay the newest entries.
+fn spawn_history_restore(
+    ui_weak: slint::Weak<MainWindow>,
+    limit: usize,
+    overrides: Arc<Mutex<Overrides>>,
+) {
+    thread::spawn(move || {
+        let saved = PlayHistory::load();
+        let skip = saved.songs.len().saturating_sub(limit);
+        let cache = LibraryCache::load();
+        // 已不存在的文件直接跳过
+        let mut history = saved.songs[skip..]
+            .iter()
+            .filter_map(|path| {
+                let song = if stream::is_url(path) {
+                    Some(stream::url_song_info(path))
+                } else {
+                    library_cache::file_stamp(Path::new(path)).and_then(|stamp| {
+                        cache.get(path, stamp).or_else(|| utils::read_meta_info(path))
+                    })
+                };
+                if song.is_none() {
+                    log::warn!("song {:?} in play history is gone, skipped", path);
+                }
+                song
+            })
+            .collect::<Vec<_>>();
+        overrides.lock().unwrap().apply(&mut history);
+        let _ = slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_weak.upgrade() {
+                let ui_state = ui.global::<UIState>();
+                // 恢复的上一首歌通常已作为当前歌曲加入历史，不重复记录
+                let played = ui_state.get_play_history().iter().collect::<Vec<_>>();
+                if history
+                    .last()
+                    .zip(played.first())
+                    .is_some_and(|(a, b)| a.song_path == b.song_path)
+                {
+                    history.pop();
+                }
+                history.extend(played);
+                // history_index 从最新的一首倒数，在前面插入不影响它
+                let excess = history.len().saturating_sub(limit.max(1));
+                history.drain(..excess);
+                ui_state.set_play_history(history.as_slice().into());
+            }
+        });
+    });
+}
+
+/// Cover of `song` from the cover cache, or the one fetched online earlier, which is then cached too
+fn cached_cover(covers: &mut CoverCache, song: &SongInfo) -> Option<CoverBuffer> {
+    covers.get(&song.song_path).or_else(|| {
+        let found = online_cover::cached(song)?;
+        Some(covers.insert_found(&song.song_path, found))
+    })
+}
+
+/// Look up the missing cover of `song` online and keep it in `covers`.
+/// It is shown, with its thumbnail passed to the media session, if it is still the current song once found
+fn fetch_online_cover(
+    ui_weak: slint::Weak<MainWindow>,
+    song: &SongInfo,
+    covers: Arc<Mutex<CoverCache>>,
+) {
+    let path = song.song_path.clone();
+    online_cover::fetch(song, move |found| {
+        let (cover, cover_file) = {
+            let mut covers = covers.lock().unwrap();
+            let cover = covers.insert_found(path.as_str(), found);
+            (cover, covers.thumbnail_file(path.as_str()))
+        };
+        // 查找期间程序可能已经退出
+        let _ = slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_weak.upgrade() {
+                let ui_state = ui.global::<UIState>();
+                let song = ui_state.get_current_song();
+                if song.song_path == path {
+                    ui_state.set_album_image(slint::Image::from_rgba8(cover));
+                    media_session::set_song(&song, ui_state.get_duration(), cover_file.as_deref());
+                }
+            }
+        });
+    });
+}
+
+/// Load `song` into `sink` paused at `progress` (secs) and show it as the current song
+fn load_paused_song(
+    ui: &MainWindow,
+    sink: &rodio::Sink,
+    song: SongInfo,
+    progress: f32,
+    covers: &Arc<Mutex<CoverCache>>,
+    replay_gain: ReplayGainMode,
+    dsp: &DspControls,
+) -> Result<(), String> {
+    let source = stream::open_decoder(&song.song_path)?;
+    // 保存的进度可能已过期（如文件被替换为更短的版本），不能跳转到曲目结尾之后
+    let duration = source.total_duration().map_or(song.duration_secs, |x| x.as_secs_f32());
+    let progress = progress.clamp(0., duration);
+    sink.clear();
+    sink.append(dsp::apply_chain(source, utils::gain_factor(&song, replay_gain), dsp));
+    sink.pause();
+    let progress = match sink.try_seek(Duration::from_secs_f32(progress)) {
+        Ok(_) => progress,
+        Err(e) => {
+            log::warn!("failed to seek <{}> to {}s: {}", song.song_path, progress, e);
+            0.
+        }
+    };
+    let ui_state = ui.global::<UIState>();
+    ui_state.set_progress(progress);
+    ui_state.set_paused(true);
+    ui_state.set_duration(song.duration_secs);
+    ui_state.set_current_song(song.clone());
+    set_lyrics(&ui_state, &utils::read_lyrics(&song.song_path));
+    let cover = cached_cover(&mut covers.lock().unwrap(), &song);
     let cover = match cover {
-        Some((buffer, width, height)) => utils::from_image_to_slint(buffer, width, height),
-        None => utils::get_default_album_cover(),
+        Some(buffer) => slint::Image::from_rgba8(buffer),
+        None => {
+            fetch_online_cover(ui.as_weak(), &song, covers.clone());
+            utils::get_default_album_cover()
+        }
     };
     ui_state.set_album_image(cover);
-    let file = std::fs::File::open(&cur_song_info.song_path)
-        .unwrap_or_else(|_| panic!("failed to open audio file: {}", cur_song_info.song_path));
-    let source = Decoder::try_from(file).expect("failed to decode audio file");
-    sink.append(source);
-    sink.pause();
-    sink.try_seek(Duration::from_secs_f32(cfg.progress)).expect("failed to seek to given position");
     let mut history = ui_state.get_play_history().iter().collect::<Vec<_>>();
-    history.push(cur_song_info.clone());
-    ui_state.set_play_history(history.as_slice().into());
+    // 恢复的播放历史通常已以这首歌结尾
+    if history.last().is_none_or(|x| x.song_path != song.song_path) {
+        history.push(song);
+        ui_state.set_play_history(history.as_slice().into());
+    }
     ui_state.set_history_index(0);
+    Ok(())
+}
+
+/// Scan `dirs` into one library on a worker thread, streaming songs into the UI as they are read.
+/// Entries may also be M3U playlists, whose songs are then added to the library. A `full` scan ignores the tags cache.
+/// Once the scan finishes, `on_done` runs on the UI thread with every song found,
+/// or the UI is reset if there is none. Nothing is applied after `cancel` is set.
+fn spawn_library_scan(
+    ui_weak: slint::Weak<MainWindow>,
+    dirs: Vec<PathBuf>,
+    full: bool,
+    overrides: Arc<Mutex<Overrides>>,
+    sink: Arc<Mutex<rodio::Sink>>,
+    cancel: Arc<AtomicBool>,
+    on_done: impl FnOnce(&MainWindow, Vec<SongInfo>) + Send + 'static,
+) {
+    thread::spawn(move || {
+        // M3U 歌单中仍然存在的歌曲直接加入曲库，不扫描文件夹
+        let (playlists, folders): (Vec<_>, Vec<_>) =
+            dirs.iter().cloned().partition(|x| playlist::is_m3u_file(x));
+        // 开启曲库数据库时先显示上次扫描的结果，扫描完成后再整体替换
+        let mut stored = if full {
+            Vec::new()
+        } else {
+            library_db::songs_in(&folders)
+        };
+        overrides.lock().unwrap().apply(&mut stored);
+        let preloaded = !stored.is_empty();
+        let cancel_clone = cancel.clone();
+        let ui_weak_clone = ui_weak.clone();
+        slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_weak_clone.upgrade()
+                && !cancel_clone.load(Ordering::Relaxed)
+            {
+                let ui_state = ui.global::<UIState>();
+                ui_state.set_library(stored.as_slice().into());
+                ui_state.set_folders(Vec::new().as_slice().into());
+                if preloaded {
+                    refresh_folder_tree(&ui_state);
+                    apply_song_filter(&ui_state);
+                } else {
+                    set_song_list(&ui_state, Vec::new());
+                }
+                ui_state.set_scan_progress(0.);
+                ui_state.set_scan_eta(SharedString::new());
+                ui_state.set_scanning(true);
+            }
+        })
+        .unwrap();
+        let mut songs = Vec::new();
+        for path in &playlists {
+            match utils::load_playlist(path) {
+                Ok(x) => songs.extend(x),
+                Err(e) => log::error!("failed to load playlist {:?}: <{}>", path, e),
+            }
+        }
+        if !folders.is_empty() {
+            let scan_start = Instant::now();
+            // 强制完整扫描时丢弃缓存，重新读取所有标签
+            let mut library_cache = if full {
+                LibraryCache::default()
+            } else {
+                LibraryCache::load()
+            };
+            let scanned =
+                utils::scan_song_list(&folders, &mut library_cache, &cancel, |mut batch, done| {
+                    overrides.lock().unwrap().apply(&mut batch);
+                    let eta = scan_eta(scan_start.elapsed(), done);
+                    let cancel = cancel.clone();
+                    let ui_weak = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        // 已被新的扫描取代时丢弃
+                        if let Some(ui) = ui_weak.upgrade()
+                            && !cancel.load(Ordering::Relaxed)
+                        {
+                            let ui_state = ui.global::<UIState>();
+                            if !preloaded {
+                                let mut library = ui_state.get_library().iter().collect::<Vec<_>>();
+                                library.extend(batch);
+                                ui_state.set_library(library.as_slice().into());
+                                apply_song_filter(&ui_state);
+                            }
+                            ui_state.set_scan_progress(done);
+                            ui_state.set_scan_eta(eta);
+                        }
+                    })
+                    .unwrap();
+                });
+            let Some(scanned) = scanned else {
+                log::info!("scan of {:?} cancelled", dirs);
+                return;
+            };
+            library_cache.save();
+            songs.extend(scanned);
+        }
+        // 同一首歌可能同时出现在歌单和文件夹中
+        let mut seen = HashSet::new();
+        songs.retain(|x| seen.insert(x.song_path.clone()));
+        overrides.lock().unwrap().apply(&mut songs);
+        // 开启过歌词搜索时，在扫描线程中顺便读取新歌曲的歌词
+        utils::index_lyrics(&songs);
+        slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_weak.upgrade()
+                && !cancel.load(Ordering::Relaxed)
+            {
+                let ui_state = ui.global::<UIState>();
+                ui_state.set_scanning(false);
+                ui_state.set_scan_progress(1.);
+                if songs.is_empty() {
+                    sink.lock().unwrap().clear();
+                    set_raw_ui_state(&ui);
+                    log::warn!("song list is empty in directories: {:?}, reset UI state", dirs);
+                    show_status(&ui, StatusKind::EmptyLibrary, dir_names(&dirs));
+                    return;
+                }
+                log::info!("loaded {} songs from directories: {:?}", songs.len(), dirs);
+                ui_state.set_library(songs.as_slice().into());
+                refresh_folder_tree(&ui_state);
+                apply_song_filter(&ui_state);
+                on_done(&ui, songs);
+            }
+        })
+        .unwrap();
+    });
+}
+
+/// Scans shorter than this don't show a time left, early estimates jump around too much
+const SCAN_ETA_MIN_ELAPSED: Duration = Duration::from_secs(2);
+
+/// Time left of a scan that took `elapsed` to get through fraction `done` of the files, empty if not known yet
+fn scan_eta(elapsed: Duration, done: f32) -> SharedString {
+    if done <= 0. || elapsed < SCAN_ETA_MIN_ELAPSED {
+        return SharedString::new();
+    }
+    utils::format_duration(elapsed.as_secs_f32() * (1. - done) / done)
+}
+
+/// Waveform previews are cached for this many tracks, the cache is dropped as a whole when full
+const WAVEFORM_CACHE_SIZE: usize = 1000;
+
+/// Show the waveform of `song_path` under the progress bar once computed on a background thread.
+/// Peaks are cached per path, and dropped if another song is playing by the time they are ready.
+fn spawn_waveform(
+    ui_weak: slint::Weak<MainWindow>,
+    song_path: SharedString,
+    duration: f32,
+    cache: Arc<Mutex<HashMap<SharedString, Vec<f32>>>>,
+) {
+    thread::spawn(move || {
+        let cached = cache.lock().unwrap().get(&song_path).cloned();
+        let peaks = cached.or_else(|| {
+            let peaks =
+                utils::compute_peaks(song_path.as_str(), duration, utils::WAVEFORM_BUCKETS)?;
+            let mut cache = cache.lock().unwrap();
+            if cache.len() >= WAVEFORM_CACHE_SIZE {
+                cache.clear();
+            }
+            cache.insert(song_path.clone(), peaks.clone());
+            Some(peaks)
+        });
+        let Some(peaks) = peaks else {
+            log::warn!("failed to compute waveform of <{}>", song_path);
+            return;
+        };
+        let _ = slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_weak.upgrade() {
+                let ui_state = ui.global::<UIState>();
+                if ui_state.get_current_song().song_path == song_path {
+                    ui_state.set_waveform(peaks.as_slice().into());
+                }
+            }
+        });
+    });
+}
+
+/// Ramp the volume of `sink` linearly from `from` to `to` over `duration`.
+/// The lock is only held for each step, so the UI timer isn't blocked during the ramp.
+fn ramp_volume(sink: &Mutex<rodio::Sink>, from: f32, to: f32, duration: Duration) {
+    const STEPS: u32 = 10;
+    for i in 1..=STEPS {
+        thread::sleep(duration / STEPS);
+        sink.lock().unwrap().set_volume(from + (to - from) * i as f32 / STEPS as f32);
+    }
+}
+
+/// Previous track still fading out after a crossfade, `None` once it stopped
+type FadingSink = Mutex<Option<Arc<rodio::Sink>>>;
+
+/// Fade out `sink` linearly over `duration`, then stop it.
+/// It is tracked in `fading` meanwhile, so pausing or stopping playback can cut the fade short
+fn fade_out_sink(sink: rodio::Sink, duration: Duration, fading: Arc<FadingSink>) {
+    const STEPS: u32 = 20;
+    let sink = Arc::new(sink);
+    // 快速连续切歌时，上一段淡出直接停止
+    if let Some(previous) = fading.lock().unwrap().replace(sink.clone()) {
+        previous.stop();
+    }
+    let start = sink.volume();
+    for i in (0..STEPS).rev() {
+        thread::sleep(duration / STEPS);
+        sink.set_volume(start * i as f32 / STEPS as f32);
+    }
+    sink.stop();
+    let mut fading = fading.lock().unwrap();
+    if fading.as_ref().is_some_and(|x| Arc::ptr_eq(x, &sink)) {
+        *fading = None;
+    }
+}
+
+/// Stop the track fading out after a crossfade, if any
+fn stop_fading(fading: &FadingSink) {
+    if let Some(sink) = fading.lock().unwrap().take() {
+        sink.stop();
+    }
+}
+
+/// Move playback to the output device called `name` (empty for default), keeping the position and pause state.
+/// The new stream is fully built before the old one is dropped, so on failure the previous device keeps playing.
+fn switch_output_device(
+    ui: &MainWindow,
+    name: &str,
+    stream: &RefCell<rodio::OutputStream>,
+    mixer: &Mutex<rodio::mixer::Mixer>,
+    sink: &Mutex<rodio::Sink>,
+    replay_gain: ReplayGainMode,
+    dsp: &DspControls,
+) -> Result<(), String> {
+    let new_stream = output::open_output_stream(name).map_err(|e| e.to_string())?;
+    let new_sink = rodio::Sink::connect_new(new_stream.mixer());
+    let mut sink_guard = sink.lock().unwrap();
+    new_sink.set_volume(sink_guard.volume());
+    new_sink.pause();
+    if !sink_guard.empty() {
+        let song = ui.global::<UIState>().get_current_song();
+        let source = stream::open_decoder(&song.song_path)?;
+        new_sink.append(dsp::apply_chain(source, utils::gain_factor(&song, replay_gain), dsp));
+        new_sink.try_seek(sink_guard.get_pos()).map_err(|e| e.to_string())?;
+        if !sink_guard.is_paused() {
+            new_sink.play();
+        }
+    }
+    sink_guard.stop();
+    *sink_guard = new_sink;
+    *mixer.lock().unwrap() = new_stream.mixer().clone();
+    stream.replace(new_stream);
+    Ok(())
+}
+
+/// Follow output device changes between two polls, `before` and `after`.
+/// With the default device selected, playback moves along when the system default changes.
+/// A selected device that disappears falls back to the default, and is switched back to once it returns.
+/// With `pause_on_unplug`, losing the device in use pauses playback so it doesn't go on through the speakers.
+fn follow_output_devices(
+    ui: &MainWindow,
+    before: &DeviceSnapshot,
+    after: &DeviceSnapshot,
+    pause_on_unplug: bool,
+    tx: &mpsc::Sender<PlayerCommand>,
+) {
+    let ui_state = ui.global::<UIState>();
+    let mut devices = vec![SharedString::new()];
+    devices.extend(after.devices.iter().map(SharedString::from));
+    ui_state.set_output_devices(devices.as_slice().into());
+    let selected = ui_state.get_output_device();
+    if pause_on_unplug && !ui_state.get_paused() && before.output_lost(after, &selected) {
+        log::info!("output device unplugged, pause playback");
+        let _ = tx.send(PlayerCommand::Pause);
+    }
+    if selected.is_empty() || !after.contains(&selected) {
+        let lost = !selected.is_empty() && before.contains(&selected);
+        if before.default != after.default || lost {
+            log::info!(
+                "output device <{}> unavailable, move to default {:?}",
+                selected,
+                after.default
+            );
+            ui.invoke_select_output_device(SharedString::new());
+        }
+        if lost {
+            ui_state.set_device_lost(selected.clone());
+        }
+    } else if !before.contains(&selected) {
+        log::info!("output device <{}> is back", selected);
+        ui.invoke_select_output_device(selected);
+    }
+}
+
+/// Pause or duck playback when another app takes audio focus (`lost`), and undo it once focus returns.
+/// `acted` remembers whether we paused/ducked, so a song paused by hand isn't resumed by a call ending.
+fn follow_audio_focus(
+    ui: &MainWindow,
+    lost: bool,
+    action: FocusLossAction,
+    acted: &AtomicBool,
+    tx: &mpsc::Sender<PlayerCommand>,
+) {
+    let ui_state = ui.global::<UIState>();
+    if lost {
+        match action {
+            FocusLossAction::Pause if !ui_state.get_paused() => {
+                log::info!("audio focus lost, pause playback");
+                let _ = tx.send(PlayerCommand::Pause);
+                acted.store(true, Ordering::Relaxed);
+            }
+            FocusLossAction::Duck => {
+                log::info!("audio focus lost, duck volume");
+                let _ = tx.send(PlayerCommand::FadeVolume(ui_state.get_volume() * DUCK_FACTOR));
+                acted.store(true, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    } else if acted.swap(false, Ordering::Relaxed) {
+        match action {
+            // 期间用户已手动继续播放时不再切换
+            FocusLossAction::Pause if ui_state.get_paused() => {
+                log::info!("audio focus regained, resume playback");
+                let _ = tx.send(PlayerCommand::Pause);
+            }
+            FocusLossAction::Duck => {
+                log::info!("audio focus regained, restore volume");
+                let _ = tx.send(PlayerCommand::FadeVolume(ui_state.get_volume()));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Show the equalizer settings, with the matching preset selected (0 for custom gains).
+/// Setting a new gains model also resets sliders the user dragged away from their binding.
+fn set_eq_ui_state(ui: &MainWindow, enabled: bool, gains: [f32; dsp::EQ_BANDS.len()]) {
+    let ui_state = ui.global::<UIState>();
+    let preset = dsp::EQ_PRESETS.iter().position(|x| *x == gains);
+    ui_state.set_eq_enabled(enabled);
+    ui_state.set_eq_preset(preset.map_or(0, |x| x as i32 + 1));
+    ui_state.set_eq_gains(gains.as_slice().into());
+}
+
+/// Config reflecting the current UI state, settings not shown in the UI are taken from `base`
+fn current_config(ui: &MainWindow, base: &Config) -> Config {
+    let ui_state = ui.global::<UIState>();
+    // 最小化时的位置没有意义（Windows 下为 -32000），沿用上次保存的位置
+    let (window_size, window_position) = (ui.window().size(), ui.window().position());
+    // 迷你播放器模式下保存完整界面的大小
+    let (window_width, window_height) = if ui_state.get_compact() {
+        (ui_state.get_expanded_width() as u32, ui_state.get_expanded_height() as u32)
+    } else {
+        (window_size.width, window_size.height)
+    };
+    let (window_x, window_y) = if ui.window().is_minimized() {
+        (base.window_x, base.window_y)
+    } else {
+        (window_position.x, window_position.y)
+    };
+    let song_dirs = song_dirs(&ui_state);
+    Config {
+        song_dir: song_dirs.first().cloned().unwrap_or_else(|| base.song_dir.clone()),
+        song_dirs,
+        current_song_path: Some(ui_state.get_current_song().song_path.as_str().into()),
+        progress: ui_state.get_progress(),
+        play_mode: ui_state.get_play_mode(),
+        sort_key: ui_state.get_sort_key(),
+        sort_ascending: ui_state.get_sort_ascending(),
+        lang: ui_state.get_lang().into(),
+        light_ui: ui_state.get_light_ui(),
+        queue_end_behavior: ui_state.get_queue_end_behavior(),
+        queue: ui_state.get_queue().iter().map(|x| x.song_path.as_str().into()).collect(),
+        queue_index: ui_state.get_queue_index() as usize,
+        show_ms: ui_state.get_show_ms(),
+        show_spectrum: ui_state.get_show_spectrum(),
+        show_folders: ui_state.get_show_folders(),
+        song_grouping: ui_state.get_song_grouping(),
+        output_device: ui_state.get_output_device().into(),
+        channel_mode: ui_state.get_channel_mode(),
+        balance: ui_state.get_balance(),
+        preamp_db: ui_state.get_preamp_db(),
+        volume: ui_state.get_volume(),
+        crossfade: config::Crossfade {
+            secs: ui_state.get_crossfade_secs(),
+            ..base.crossfade.clone()
+        },
+        equalizer: config::Equalizer {
+            enabled: ui_state.get_eq_enabled(),
+            gains: ui_state.get_eq_gains().iter().collect(),
+        },
+        window_width,
+        window_height,
+        compact: ui_state.get_compact(),
+        always_on_top: ui_state.get_always_on_top(),
+        window_x,
+        window_y,
+        ..base.clone()
+    }
+}
+
+/// Tell the user the app crashed and where its log is.
+/// The dialog is native, so it still shows when the Slint event loop is already gone.
+fn show_panic_dialog(message: &str) {
+    // 对话框本身出错再次 panic 时不再弹出
+    static SHOWN: AtomicBool = AtomicBool::new(false);
+    if SHOWN.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    rfd::MessageDialog::new()
+        .set_level(rfd::MessageLevel::Error)
+        .set_title("Zeedle crashed")
+        .set_description(format!(
+            "{}\n\nPlease report this bug with the log file:\n{}",
+            message,
+            logger::log_path().display()
+        ))
+        .set_buttons(rfd::MessageButtons::Ok)
+        .show();
 }
 
 fn main() {
     let app_start = Instant::now();
-    logger::init_default_logger(None::<PathBuf>);
     // when panics happen, auto port errors to log
+    // 最先设置，读取配置时的 panic 也能被记录；日志尚未初始化时输出到 stderr
     std::panic::set_hook(Box::new(|info| {
-        log::error!("{}", info);
+        if log::max_level() == log::LevelFilter::Off {
+            eprintln!("{}", info);
+        } else {
+            log::error!("{}", info);
+        }
+        // 只有主线程 panic 时程序才会退出，此时弹出原生对话框告知用户日志位置，否则窗口会无声消失
+        if thread::current().name() == Some("main") {
+            show_panic_dialog(&info.to_string());
+        }
     }));
+    // 先读取配置，日志级别由配置决定
+    let (cfg, load_warnings) = Config::load();
+    let log_level = cfg.log_level.parse::<log::LevelFilter>().ok();
+    logger::init_default_logger(
+        None::<PathBuf>,
+        log_level.unwrap_or(log::LevelFilter::Info),
+        cfg.log_max_mb * 1024 * 1024,
+        cfg.log_files,
+    );
+    // 读取配置时日志尚未初始化，此时才记录发现的问题
+    for warning in load_warnings {
+        log::warn!("{}", warning);
+    }
+    if log_level.is_none() {
+        log::warn!("invalid log level <{}> in config, using info", cfg.log_level);
+    }
     let ins = single_instance::SingleInstance::new("Zeedle Music Player").unwrap();
     if !ins.is_single() {
-        log::warn!("Vanilla player can only run one instance !");
+        // 把要打开的文件交给正在运行的实例，并让它显示窗口
+        if ipc::forward_to_running(cli_file().as_deref()) {
+            log::info!("forwarded to the running instance");
+        } else {
+            log::warn!("Vanilla player can only run one instance !");
+        }
         return;
     }
-    let mut stream_handle = rodio::OutputStreamBuilder::from_default_device()
-        .expect("no output device available")
-        .with_buffer_size(cpal::BufferSize::Fixed(4096))
-        .open_stream()
-        .expect("failed to open output stream");
-    stream_handle.log_on_drop(false);
+    // 配置的输出设备不可用时回退到默认设备，并在设置页提示
+    let mut device_error = None;
+    let stream_handle = output::open_output_stream(&cfg.output_device)
+        .or_else(|e| {
+            log::warn!("failed to open output device <{}>: {}", cfg.output_device, e);
+            device_error = Some(format!("{}: {}", cfg.output_device, e));
+            output::open_output_stream("")
+        })
+        .expect("no output device available");
     let _sink = rodio::Sink::connect_new(stream_handle.mixer());
     let sink = Arc::new(Mutex::new(_sink));
+    let mixer = Arc::new(Mutex::new(stream_handle.mixer().clone()));
+    // 交叉淡入淡出中正在淡出的上一首，暂停、停止和切换设备时一并停止
+    let fading = Arc::new(FadingSink::default());
+    // 输出流不能跨线程，只在 UI 线程中持有和替换
+    let stream_handle = Rc::new(RefCell::new(stream_handle));
+    utils::set_max_texture_size(cfg.max_texture_size);
+    utils::set_audio_formats(&cfg.formats);
+    utils::set_scan_threads(cfg.scan_threads);
+    if cfg.online_covers {
+        online_cover::enable();
+    }
+    if cfg.library_db {
+        library_db::enable();
+    }
+    let cover_cache = Arc::new(Mutex::new(CoverCache::new(
+        config::get_cache_dir().join("covers"),
+        cfg.cover_cache_mb,
+    )));
+    let overrides = Arc::new(Mutex::new(Overrides::load()));
+    let album_resume = Arc::new(Mutex::new(AlbumResume::load()));
+    // 睡眠定时的截止时间，由播放线程设置，UI 定时器检查
+    let sleep_deadline = Arc::new(Mutex::new(None::<Instant>));
+    let dsp_controls =
+        DspControls::new(cfg.channel_mode, cfg.balance, cfg.preamp_db, &cfg.equalizer);
     // 创建消息通道 ui --> backend
     let (tx, rx) = mpsc::channel::<PlayerCommand>();
+    // 注册全局媒体键，窗口不在前台时也能控制播放；注册失败时只记录警告
+    let _media_keys = media_keys::register(tx.clone());
     // 初始化 UI 状态
     let ui = MainWindow::new().expect("failed to create UI");
-    set_start_ui_state(&ui, &sink.lock().unwrap());
+    set_start_ui_state(
+        &ui,
+        &sink.lock().unwrap(),
+        &cfg,
+        &cover_cache,
+        &overrides.lock().unwrap(),
+        &dsp_controls,
+    );
+    // 播放历史要读取多首歌曲的标签，不在启动时阻塞 UI 线程
+    spawn_history_restore(ui.as_weak(), cfg.history_size, overrides.clone());
+    ui.global::<UIState>().set_output_device(cfg.output_device.clone().into());
+    if let Some(e) = device_error {
+        ui.global::<UIState>().set_device_error(e.into());
+    }
+    ui.global::<UIState>().set_channel_mode(cfg.channel_mode);
+    ui.global::<UIState>().set_balance(cfg.balance.clamp(-1., 1.));
+    ui.global::<UIState>().set_preamp_db(cfg.preamp_db);
+    ui.global::<UIState>().set_seek_step(cfg.seek_step_secs.max(1.));
+    ui.global::<UIState>().set_crossfade_secs(cfg.crossfade.secs.clamp(0., MAX_CROSSFADE_SECS));
+    set_eq_ui_state(&ui, cfg.equalizer.enabled, cfg.equalizer.band_gains());
+    let last_scan = Arc::new(AtomicU64::new(utils::unix_secs(SystemTime::now())));
+    // 当前扫描的取消标记，新的扫描开始时置位，旧扫描的结果随之作废
+    let mut scan_cancel = Arc::new(AtomicBool::new(false));
+    {
+        let sink = sink.clone();
+        let cover_cache = cover_cache.clone();
+        let dsp = dsp_controls.clone();
+        let replay_gain = cfg.replay_gain;
+        spawn_library_scan(
+            ui.as_weak(),
+            cfg.song_dirs.clone(),
+            false,
+            overrides.clone(),
+            sink.clone(),
+            scan_cancel.clone(),
+            move |ui, songs| {
+                let ui_state = ui.global::<UIState>();
+                let cur_path = ui_state.get_current_song().song_path;
+                if let Some(cur_song) = songs.iter().find(|x| x.song_path == cur_path) {
+                    ui_state.set_current_song(cur_song.clone());
+                }
+                // 上次的歌曲已恢复，或扫描期间已开始播放时保持不变，否则载入列表中的第一首
+                if !cur_path.is_empty() {
+                    return;
+                }
+                let Some(first_song) = ui_state.get_song_list().row_data(0) else {
+                    return;
+                };
+                let song_path = first_song.song_path.clone();
+                if let Err(e) = load_paused_song(
+                    ui,
+                    &sink.lock().unwrap(),
+                    first_song,
+                    0.,
+                    &cover_cache,
+                    replay_gain,
+                    &dsp,
+                ) {
+                    log::warn!("failed to load <{}>: {}", song_path, e);
+                }
+            },
+        );
+    }
 
     // 播放线程
     let ui_weak = ui.as_weak();
     let sink_clone = sink.clone();
+    let fading_clone = fading.clone();
+    let cover_cache_clone = cover_cache.clone();
+    let overrides_clone = overrides.clone();
+    let album_resume_clone = album_resume.clone();
+    let sleep_deadline_clone = sleep_deadline.clone();
+    let dsp_clone = dsp_controls.clone();
+    let radio_weights = cfg.radio.clone();
+    let history_size = cfg.history_size;
+    let notify_songs = cfg.notifications;
+    let shuffle_bag = Arc::new(Mutex::new(ShuffleBag::default()));
+    let replay_gain = cfg.replay_gain;
+    let shuffle_scope = cfg.shuffle_scope;
+    let mut crossfade = cfg.crossfade.clone();
+    let waveforms = Arc::new(Mutex::new(HashMap::new()));
+    let pause_fade = Duration::from_millis(cfg.pause_fade_ms);
+    let mixer_clone = mixer.clone();
+    let last_scan_clone = last_scan.clone();
+    let mut scanned_dirs = cfg.song_dirs.clone();
     thread::spawn(move || {
         log::info!("player thread running...");
-        while let Ok(cmd) = rx.recv() {
+        // 连续无法播放的歌曲数，超过上限后不再自动跳过，避免整个目录都损坏时无限循环
+        let mut failed_in_row = 0;
+        // 合并刷新请求时提前收到的其他命令，在下一轮处理
+        let mut pending = None;
+        while let Some(cmd) = pending.take().or_else(|| rx.recv().ok()) {
+            let (cmd, next) = debounce_refresh(cmd, &rx);
+            pending = next;
+            logger::log_command(cmd.name());
+            // 提前 continue 的命令也会在本轮结束时记录状态变化
+            let _transition = log::log_enabled!(target: logger::STATE_TARGET, log::Level::Debug)
+                .then(|| TransitionLog::start(&ui_weak, &cmd));
             match cmd {
                 PlayerCommand::Play(song_info, trigger) => {
-                    let file = std::fs::File::open(&song_info.song_path)
-                        .expect("failed to open audio file");
-                    let source = Decoder::try_from(file).expect("failed to decode audio file");
+                    let source = match stream::open_decoder(&song_info.song_path) {
+                        Ok(source) => {
+                            failed_in_row = 0;
+                            source
+                        }
+                        Err(e) => {
+                            log::error!("failed to play <{}>: {}", song_info.song_path, e);
+                            failed_in_row += 1;
+                            // 网络地址无法播放时只提示错误，不跳到列表中的下一首
+                            let is_url = stream::is_url(&song_info.song_path);
+                            let skip = failed_in_row < MAX_SKIPS_ON_ERROR && !is_url;
+                            let missing =
+                                !is_url && !std::path::Path::new(&song_info.song_path).exists();
+                            let ui_weak = ui_weak.clone();
+                            slint::invoke_from_event_loop(move || {
+                                if let Some(ui) = ui_weak.upgrade() {
+                                    if missing {
+                                        show_status(
+                                            &ui,
+                                            StatusKind::FileMissing,
+                                            song_info.song_path.clone(),
+                                        );
+                                    } else {
+                                        show_status(
+                                            &ui,
+                                            StatusKind::PlayFailed,
+                                            format!("{}: {}", song_info.song_name, e).into(),
+                                        );
+                                    }
+                                    if skip {
+                                        // 以失败的歌曲为起点取下一首，否则仍从上一首算起会再次选中它
+                                        ui.global::<UIState>().set_current_song(song_info);
+                                        ui.invoke_play_next();
+                                    } else {
+                                        log::warn!("too many unplayable songs in a row, stop");
+                                    }
+                                }
+                            })
+                            .unwrap();
+                            continue;
+                        }
+                    };
                     let lyrics = utils::read_lyrics(&song_info.song_path);
                     let dura = source.total_duration().map(|d| d.as_secs_f32()).unwrap_or(0.0);
-                    let sink_guard = sink_clone.lock().unwrap();
-                    sink_guard.clear();
-                    sink_guard.append(source);
-                    sink_guard.play();
+                    let source = dsp::apply_chain(
+                        source,
+                        utils::gain_factor(&song_info, replay_gain),
+                        &dsp_clone,
+                    );
+                    let fade = crossfade.fade_for(trigger);
+                    let mut sink_guard = sink_clone.lock().unwrap();
+                    if fade.is_zero() || sink_guard.empty() || sink_guard.is_paused() {
+                        stop_fading(&fading_clone);
+                        sink_guard.clear();
+                        sink_guard.append(source);
+                        sink_guard.play();
+                    } else {
+                        // 交叉淡入淡出：新歌曲在新的 sink 中淡入，旧 sink 在后台淡出后停止
+                        let new_sink = rodio::Sink::connect_new(&mixer_clone.lock().unwrap());
+                        new_sink.set_volume(sink_guard.volume());
+                        new_sink.append(source.fade_in(fade));
+                        let old_sink = std::mem::replace(&mut *sink_guard, new_sink);
+                        let fading = fading_clone.clone();
+                        thread::spawn(move || fade_out_sink(old_sink, fade, fading));
+                        log::debug!("crossfade {:?} on <{:?}>", fade, trigger);
+                    }
                     log::info!("start playing: <{}>", song_info.song_name);
-                    let cover = utils::read_album_cover(&song_info.song_path);
+                    let (cover, cover_file) = {
+                        let mut cover_cache = cover_cache_clone.lock().unwrap();
+                        let cover = cached_cover(&mut cover_cache, &song_info);
+                        (cover, cover_cache.thumbnail_file(&song_info.song_path))
+                    };
+                    if cover.is_none() {
+                        fetch_online_cover(ui_weak.clone(), &song_info, cover_cache_clone.clone());
+                    }
+                    let (waveform_ui, song_path) = (ui_weak.clone(), song_info.song_path.clone());
                     let ui_weak = ui_weak.clone();
+                    let album_resume = album_resume_clone.clone();
                     slint::invoke_from_event_loop(move || {
                         if let Some(ui) = ui_weak.upgrade() {
                             let ui_state = ui.global::<UIState>();
+                            // 换曲时记录上一首在其专辑中的位置，并把新歌曲记为所在专辑的续播点
+                            let mut album_resume = album_resume.lock().unwrap();
+                            album_resume.record(
+                                &ui_state.get_current_song().song_path,
+                                ui_state.get_progress(),
+                                ui_state.get_duration(),
+                            );
+                            album_resume.record(&song_info.song_path, 0., dura);
                             match trigger {
                                 TriggerSource::ClickItem => {
-                                    let mut history =
-                                        ui_state.get_play_history().iter().collect::<Vec<_>>();
-                                    history.push(song_info.clone());
-                                    ui_state.set_play_history(history.as_slice().into());
+                                    push_history(&ui_state, song_info.clone(), history_size);
                                     ui_state.set_history_index(0);
                                 }
                                 TriggerSource::Prev => {
@@ -185,18 +1407,17 @@ fn main() {
                                     ui_state
                                         .set_history_index(new_index.min(history.len() as i32 - 1));
                                 }
-                                TriggerSource::Next => {
+                                TriggerSource::Next | TriggerSource::Auto => {
                                     if ui_state.get_history_index() > 0 {
                                         ui_state
                                             .set_history_index(ui_state.get_history_index() - 1);
                                     } else {
                                         if ui_state.get_play_mode() != PlayMode::Recursive {
-                                            let mut history = ui_state
-                                                .get_play_history()
-                                                .iter()
-                                                .collect::<Vec<_>>();
-                                            history.push(song_info.clone());
-                                            ui_state.set_play_history(history.as_slice().into());
+                                            push_history(
+                                                &ui_state,
+                                                song_info.clone(),
+                                                history_size,
+                                            );
                                         }
                                         ui_state.set_history_index(0);
                                     }
@@ -207,29 +1428,44 @@ fn main() {
                             ui_state.set_paused(false);
                             ui_state.set_progress(0.0);
                             ui_state.set_duration(dura);
+                            media_session::set_song(&song_info, dura, cover_file.as_deref());
+                            discord::set_song(&song_info, dura);
+                            tray::set_song(&song_info);
+                            // 窗口在前台时手动切歌已经能看到，不再通知
+                            if notify_songs
+                                && (trigger == TriggerSource::Auto || !window_focused(&ui))
+                            {
+                                notification::now_playing(&song_info, cover_file.as_deref());
+                            }
                             ui_state.set_user_listening(true);
-                            ui_state.set_lyrics(lyrics.as_slice().into());
+                            set_lyrics(&ui_state, &lyrics);
                             ui_state.set_lyric_viewport_y(0.);
+                            ui_state.set_current_lyric(-1);
+                            ui_state.set_loop_a(-1.);
+                            ui_state.set_loop_b(-1.);
+                            ui_state.set_loop_edit(false);
+                            ui_state.set_waveform(Default::default());
                             let cover = match cover {
-                                Some((buffer, width, height)) => {
-                                    utils::from_image_to_slint(buffer, width, height)
-                                }
+                                Some(buffer) => slint::Image::from_rgba8(buffer),
                                 None => utils::get_default_album_cover(),
                             };
                             ui_state.set_album_image(cover);
-
-                            log::debug!(
-                                "{:?} / {}",
-                                ui_state
-                                    .get_play_history()
-                                    .iter()
-                                    .map(|x| x.id)
-                                    .collect::<Vec<_>>(),
-                                ui_state.get_history_index()
-                            );
                         }
                     })
                     .unwrap();
+                    // 波形在清空旧波形之后才会送达，事件循环按顺序执行
+                    spawn_waveform(waveform_ui, song_path, dura, waveforms.clone());
+                }
+                PlayerCommand::PlayUrl(url) => {
+                    if !stream::is_url(&url) {
+                        log::warn!("not an http(s) url: <{}>", url);
+                        continue;
+                    }
+                    // 远程歌曲不读取标签，其余流程与本地歌曲相同
+                    pending = Some(PlayerCommand::Play(
+                        stream::url_song_info(&url),
+                        TriggerSource::ClickItem,
+                    ));
                 }
                 PlayerCommand::Pause => {
                     let sink_guard = sink_clone.lock().unwrap();
@@ -250,10 +1486,26 @@ fn main() {
                         .unwrap();
                     } else {
                         let paused = sink_guard.is_paused();
+                        let volume = sink_guard.volume();
+                        // 暂停/继续时音量渐变，结束后恢复用户音量
                         if paused {
+                            if !pause_fade.is_zero() {
+                                sink_guard.set_volume(0.);
+                            }
                             sink_guard.play();
+                            drop(sink_guard);
+                            if !pause_fade.is_zero() {
+                                ramp_volume(&sink_clone, 0., volume, pause_fade);
+                            }
                         } else {
+                            drop(sink_guard);
+                            stop_fading(&fading_clone);
+                            if !pause_fade.is_zero() {
+                                ramp_volume(&sink_clone, volume, 0., pause_fade);
+                            }
+                            let sink_guard = sink_clone.lock().unwrap();
                             sink_guard.pause();
+                            sink_guard.set_volume(volume);
                         }
                         slint::invoke_from_event_loop(move || {
                             if let Some(ui) = ui_weak.upgrade() {
@@ -281,11 +1533,69 @@ fn main() {
                         }
                         Err(e) => {
                             log::error!("Failed to seek: <{}>", e);
+                            let ui_weak = ui_weak.clone();
+                            slint::invoke_from_event_loop(move || {
+                                if let Some(ui) = ui_weak.upgrade() {
+                                    show_status(&ui, StatusKind::SeekFailed, e.to_shared_string());
+                                }
+                            })
+                            .unwrap();
                         }
                     }
                 }
-                PlayerCommand::PlayNext => {
+                PlayerCommand::SeekRelative(delta) => {
+                    let sink_guard = sink_clone.lock().unwrap();
+                    if sink_guard.empty() {
+                        continue;
+                    }
+                    let pos = sink_guard.get_pos().as_secs_f32();
                     let ui_weak = ui_weak.clone();
+                    // 时长只有 UI 知道，在 UI 线程中限制范围后按绝对位置跳转
+                    slint::invoke_from_event_loop(move || {
+                        if let Some(ui) = ui_weak.upgrade() {
+                            let ui_state = ui.global::<UIState>();
+                            let target = (pos + delta).clamp(0., ui_state.get_duration().max(0.));
+                            // 立即更新进度条，避免下一次定时刷新前跳回原位
+                            ui_state.set_progress(target);
+                            ui.invoke_change_progress(target);
+                        }
+                    })
+                    .unwrap();
+                }
+                PlayerCommand::SetSleepTimer(after) => {
+                    *sleep_deadline_clone.lock().unwrap() = after.map(|x| Instant::now() + x);
+                    log::info!("sleep timer set to {:?}", after);
+                }
+                PlayerCommand::SetVolume(volume) => {
+                    // 切歌与切换设备时新的 sink 会沿用当前音量
+                    sink_clone.lock().unwrap().set_volume(volume.clamp(0., 2.));
+                }
+                PlayerCommand::FadeVolume(volume) => {
+                    let from = sink_clone.lock().unwrap().volume();
+                    ramp_volume(&sink_clone, from, volume.clamp(0., 2.), FOCUS_FADE);
+                }
+                PlayerCommand::SetCrossfade(secs) => {
+                    crossfade.secs = secs.clamp(0., MAX_CROSSFADE_SECS);
+                    log::info!("crossfade set to {:.1}s", crossfade.secs);
+                }
+                PlayerCommand::SetEqBand(band, db) => {
+                    log::debug!("equalizer band {} set to {:.1} dB", band, db);
+                    dsp_clone.set_eq_band(band, db);
+                }
+                PlayerCommand::SetEqEnabled(enabled) => {
+                    log::info!("equalizer enabled: {}", enabled);
+                    dsp_clone.set_eq_enabled(enabled);
+                }
+                PlayerCommand::SetBalance(balance) => {
+                    log::debug!("balance set to {:.2}", balance);
+                    dsp_clone.set_balance(balance);
+                }
+                PlayerCommand::PlayNext(trigger) => {
+                    let ui_weak = ui_weak.clone();
+                    let sink_clone = sink_clone.clone();
+                    let fading = fading_clone.clone();
+                    let radio_weights = radio_weights.clone();
+                    let shuffle_bag = shuffle_bag.clone();
                     slint::invoke_from_event_loop(move || {
                         if let Some(ui) = ui_weak.upgrade() {
                             let ui_state = ui.global::<UIState>();
@@ -299,11 +1609,50 @@ fn main() {
                                     .rev()
                                     .nth((ui_state.get_history_index() - 1) as usize)
                                 {
-                                    ui.invoke_play(song.clone(), TriggerSource::Next);
+                                    logger::log_decision(
+                                        "PlayNext",
+                                        &format!("source=history path={}", song.song_path),
+                                    );
+                                    ui.invoke_play(song.clone(), trigger);
                                 } else {
                                     log::warn!("failed to play next song in history");
                                 }
                             } else {
+                                // 其次播放队列中的下一首
+                                let mut queue = ui_state.get_queue().iter().collect::<Vec<_>>();
+                                let mut queue_index = ui_state.get_queue_index() as usize;
+                                if !queue.is_empty() && queue_index >= queue.len() {
+                                    let behavior = ui_state.get_queue_end_behavior();
+                                    log::info!("queue exhausted, behavior: <{:?}>", behavior);
+                                    match behavior {
+                                        QueueEndBehavior::Loop => queue_index = 0,
+                                        QueueEndBehavior::Stop => {
+                                            ui_state.set_queue(Vec::new().as_slice().into());
+                                            ui_state.set_queue_index(0);
+                                            sink_clone.lock().unwrap().clear();
+                                            stop_fading(&fading);
+                                            ui_state.set_paused(true);
+                                            ui_state.set_progress(0.0);
+                                            logger::log_decision("PlayNext", "source=queue stop");
+                                            return;
+                                        }
+                                        QueueEndBehavior::Continue => {
+                                            // 清空队列，按播放模式从最后一首队列歌曲处继续
+                                            queue.clear();
+                                            ui_state.set_queue(Vec::new().as_slice().into());
+                                            ui_state.set_queue_index(0);
+                                        }
+                                    }
+                                }
+                                if let Some(song) = queue.get(queue_index) {
+                                    ui_state.set_queue_index(queue_index as i32 + 1);
+                                    logger::log_decision(
+                                        "PlayNext",
+                                        &format!("source=queue path={}", song.song_path),
+                                    );
+                                    ui.invoke_play(song.clone(), trigger);
+                                    return;
+                                }
                                 // 否则根据播放模式获取下一首
                                 log::info!("playing next from play mode");
                                 let song_list: Vec<_> = ui_state.get_song_list().iter().collect();
@@ -311,23 +1660,133 @@ fn main() {
                                     log::warn!("song list is empty, can't play next");
                                     return;
                                 }
-                                let mut rng = rand::rng();
-                                let next_id1 = rng.random_range(..song_list.len());
-                                let id = ui_state.get_current_song().id as usize;
-                                let mut next_id2 = if id + 1 >= song_list.len() {
-                                    0
-                                } else {
-                                    id + 1
-                                };
-                                next_id2 = next_id2.min(song_list.len() - 1);
+                                if ui_state.get_play_mode() == PlayMode::Random
+                                    && shuffle_scope == ShuffleScope::Library
+                                {
+                                    // 按洗牌顺序从整个曲库选取，若该歌曲在可见列表中则使用列表中的条目以保持 id 一致
+                                    let library = ui_state
+                                        .get_library()
+                                        .iter()
+                                        .filter(|x| !x.skip_autoplay)
+                                        .collect::<Vec<_>>();
+                                    let candidates = library
+                                        .iter()
+                                        .map(|x| x.song_path.clone())
+                                        .collect::<Vec<_>>();
+                                    let next_path = shuffle_bag
+                                        .lock()
+                                        .unwrap()
+                                        .next(&candidates, &ui_state.get_current_song().song_path);
+                                    if let Some(song) = next_path
+                                        .and_then(|p| library.iter().find(|x| x.song_path == p))
+                                    {
+                                        let song = song_list
+                                            .iter()
+                                            .find(|x| x.song_path == song.song_path)
+                                            .unwrap_or(song)
+                                            .clone();
+                                        logger::log_decision(
+                                            "PlayNext",
+                                            &format!("source=Library path={}", song.song_path),
+                                        );
+                                        ui.invoke_play(song, trigger);
+                                        return;
+                                    }
+                                }
+                                // 按路径定位当前歌曲，队列中的歌曲可能来自排序前的列表
+                                let cur_song = ui_state.get_current_song();
+                                let id = utils::song_index(&song_list, &cur_song);
+                                // 跳过被标记为不自动播放的歌曲
+                                let next_in_order = utils::next_playable(&song_list, id);
                                 let next_id = match ui_state.get_play_mode() {
-                                    PlayMode::InOrder => next_id2,
-                                    PlayMode::Random => next_id1,
-                                    PlayMode::Recursive => id,
+                                    PlayMode::InOrder => {
+                                        // 顺序播放到列表末尾时停止，手动切换下一首仍回到开头
+                                        if trigger == TriggerSource::Auto
+                                            && next_in_order
+                                                .is_some_and(|next| id.is_some_and(|id| next <= id))
+                                        {
+                                            // 交叉淡入淡出会提前触发，此时让最后一首播完
+                                            if sink_clone.lock().unwrap().empty() {
+                                                ui_state.set_paused(true);
+                                                ui_state.set_user_listening(false);
+                                                logger::log_decision(
+                                                    "PlayNext",
+                                                    "source=InOrder end of list stop",
+                                                );
+                                            }
+                                            return;
+                                        }
+                                        next_in_order
+                                    }
+                                    PlayMode::RepeatAll => next_in_order,
+                                    PlayMode::Random => {
+                                        // 洗牌播放：每轮中每首歌只播放一次，且不会连续重复
+                                        let candidates = song_list
+                                            .iter()
+                                            .filter(|x| !x.skip_autoplay)
+                                            .map(|x| x.song_path.clone())
+                                            .collect::<Vec<_>>();
+                                        shuffle_bag
+                                            .lock()
+                                            .unwrap()
+                                            .next(&candidates, &cur_song.song_path)
+                                            .and_then(|p| {
+                                                song_list.iter().position(|x| x.song_path == p)
+                                            })
+                                    }
+                                    // 单曲循环在自然播完时直接重播当前歌曲，它不一定在当前显示的列表中；
+                                    // 手动下一首和跳过无法播放的歌曲时按列表顺序前进
+                                    PlayMode::Recursive
+                                        if trigger == TriggerSource::Auto
+                                            && !cur_song.song_path.is_empty() =>
+                                    {
+                                        logger::log_decision(
+                                            "PlayNext",
+                                            &format!(
+                                                "source=Recursive path={}",
+                                                cur_song.song_path
+                                            ),
+                                        );
+                                        ui.invoke_play(cur_song.clone(), trigger);
+                                        return;
+                                    }
+                                    PlayMode::Recursive => next_in_order,
+                                    PlayMode::Radio => {
+                                        let history =
+                                            ui_state.get_play_history().iter().collect::<Vec<_>>();
+                                        let mut recent = history
+                                            .iter()
+                                            .rev()
+                                            .take(radio_weights.recent_exclude)
+                                            .map(|x| x.song_path.clone())
+                                            .collect::<Vec<_>>();
+                                        recent.push(ui_state.get_current_song().song_path);
+                                        utils::pick_radio_song(
+                                            &song_list,
+                                            &ui_state.get_radio_seed(),
+                                            &recent,
+                                            &radio_weights,
+                                        )
+                                        .or(next_in_order)
+                                    }
+                                };
+                                let Some(next_id) = next_id else {
+                                    log::warn!(
+                                        "every song is skipped in autoplay, can't play next"
+                                    );
+                                    return;
                                 };
                                 if let Some(next_song) = song_list.get(next_id) {
+                                    logger::log_decision(
+                                        "PlayNext",
+                                        &format!(
+                                            "source={:?} path={}",
+                                            ui_state.get_play_mode(),
+                                            next_song.song_path
+                                        ),
+                                    );
                                     let song_to_play = next_song.clone();
-                                    ui.invoke_play(song_to_play.clone(), TriggerSource::Next);
+                                    ui.invoke_play(song_to_play.clone(), trigger);
                                 } else {
                                     log::warn!("failed to play next from play mode");
                                 }
@@ -353,9 +1812,17 @@ fn main() {
                                 .rev()
                                 .nth((ui_state.get_history_index() + 1) as usize)
                             {
+                                logger::log_decision(
+                                    "PlayPrev",
+                                    &format!("source=history path={}", song.song_path),
+                                );
                                 ui.invoke_play(song.clone(), TriggerSource::Prev);
                                 log::info!("playing prev from history");
                             } else {
+                                logger::log_decision(
+                                    "PlayPrev",
+                                    &format!("source=replay path={}", cur_song.song_path),
+                                );
                                 ui.invoke_play(cur_song, TriggerSource::Prev);
                                 log::info!("can't get earlier history, fall back to replay oldest history song...");
                             }
@@ -374,72 +1841,343 @@ fn main() {
                     })
                     .unwrap();
                 }
-                PlayerCommand::RefreshSongList(path) => {
-                    let new_list = utils::read_song_list(&path, SortKey::BySongName, true);
+                PlayerCommand::RefreshSongList(dirs, full) => {
+                    let scan_start = SystemTime::now();
                     let ui_weak = ui_weak.clone();
                     let sink_clone = sink_clone.clone();
+                    // 歌单没有增量刷新，总是重新读取
+                    if full || dirs != scanned_dirs || dirs.iter().any(playlist::is_m3u_file) {
+                        scan_cancel.store(true, Ordering::Relaxed);
+                        scan_cancel = Arc::new(AtomicBool::new(false));
+                        let last_scan = last_scan_clone.clone();
+                        spawn_library_scan(
+                            ui_weak,
+                            dirs.clone(),
+                            full,
+                            overrides_clone.clone(),
+                            sink_clone,
+                            scan_cancel.clone(),
+                            move |ui, songs| {
+                                let ui_state = ui.global::<UIState>();
+                                let cur_path = ui_state.get_current_song().song_path;
+                                // 扫描期间已从新列表中开始播放的歌曲保持不变
+                                match songs.iter().find(|x| x.song_path == cur_path) {
+                                    Some(cur_song) => ui_state.set_current_song(cur_song.clone()),
+                                    None => {
+                                        let first_song = ui_state
+                                            .get_song_list()
+                                            .row_data(0)
+                                            .unwrap_or(songs[0].clone());
+                                        ui.invoke_play(first_song, TriggerSource::ClickItem);
+                                    }
+                                }
+                                last_scan.store(utils::unix_secs(scan_start), Ordering::Relaxed);
+                            },
+                        );
+                    } else {
+                        // 增量刷新：只解析上次扫描后新增/修改的文件，并剔除已删除的文件
+                        // 在后台线程中进行，大曲库遍历期间不阻塞播放命令
+                        let last_scan = last_scan_clone.clone();
+                        let overrides = overrides_clone.clone();
+                        let cancel = scan_cancel.clone();
+                        let dirs = dirs.clone();
+                        thread::spawn(move || {
+                            // 取出曲库中已有的歌曲，移动或重命名进来的文件保留了旧的时间，要据此找出
+                            let (known_tx, known_rx) = mpsc::channel();
+                            let ui_known = ui_weak.clone();
+                            slint::invoke_from_event_loop(move || {
+                                if let Some(ui) = ui_known.upgrade() {
+                                    let library = ui.global::<UIState>().get_library();
+                                    let _ = known_tx.send(
+                                        library.iter().map(|x| x.song_path).collect::<HashSet<_>>(),
+                                    );
+                                }
+                            })
+                            .unwrap();
+                            let Ok(known) = known_rx.recv() else {
+                                return;
+                            };
+                            let since = SystemTime::UNIX_EPOCH
+                                + Duration::from_secs(last_scan.load(Ordering::Relaxed));
+                            let (present, mut changed) =
+                                utils::scan_changed_songs(&dirs, since, &known);
+                            let dir_names = dir_names(&dirs);
+                            overrides.lock().unwrap().apply(&mut changed);
+                            utils::forget_lyrics(&changed);
+                            utils::index_lyrics(&changed);
+                            log::info!(
+                                "incremental refresh: {} files found, {} new or changed",
+                                present.len(),
+                                changed.len()
+                            );
+                            slint::invoke_from_event_loop(move || {
+                                // 已被新的完整扫描取代时丢弃
+                                if let Some(ui) = ui_weak.upgrade()
+                                    && !cancel.load(Ordering::Relaxed)
+                                {
+                                    let ui_state = ui.global::<UIState>();
+                                    let changed_paths = changed
+                                        .iter()
+                                        .map(|x| x.song_path.clone())
+                                        .collect::<HashSet<_>>();
+                                    let mut song_list = ui_state
+                                        .get_library()
+                                        .iter()
+                                        .filter(|x| {
+                                            present.contains(&x.song_path)
+                                                && !changed_paths.contains(&x.song_path)
+                                        })
+                                        .collect::<Vec<_>>();
+                                    song_list.extend(changed);
+                                    if song_list.is_empty() {
+                                        let sink_guard = sink_clone.lock().unwrap();
+                                        sink_guard.clear();
+                                        set_raw_ui_state(&ui);
+                                        log::warn!("song list is empty, reset UI state");
+                                        show_status(&ui, StatusKind::EmptyLibrary, dir_names);
+                                        return;
+                                    }
+                                    utils::sort_songs(
+                                        &mut song_list,
+                                        ui_state.get_sort_key(),
+                                        ui_state.get_sort_ascending(),
+                                    );
+                                    let cur_path = ui_state.get_current_song().song_path;
+                                    if let Some(cur_song) =
+                                        song_list.iter().find(|x| x.song_path == cur_path)
+                                    {
+                                        ui_state.set_current_song(cur_song.clone());
+                                    }
+                                    ui_state.set_library(song_list.as_slice().into());
+                                    refresh_folder_tree(&ui_state);
+                                    apply_song_filter(&ui_state);
+                                }
+                            })
+                            .unwrap();
+                            last_scan.store(utils::unix_secs(scan_start), Ordering::Relaxed);
+                        });
+                    }
+                    scanned_dirs = dirs;
+                }
+                PlayerCommand::SortSongList(key, ascending) => {
+                    let ui_weak = ui_weak.clone();
                     slint::invoke_from_event_loop(move || {
                         if let Some(ui) = ui_weak.upgrade() {
                             let ui_state = ui.global::<UIState>();
-                            ui_state.set_song_list(new_list.as_slice().into());
-                            ui_state.set_sort_key(SortKey::BySongName);
-                            ui_state.set_sort_ascending(true);
-                            if let Some(first_song) = new_list.first() {
-                                ui.invoke_play(first_song.clone(), TriggerSource::ClickItem);
-                            } else {
-                                let sink_guard = sink_clone.lock().unwrap();
-                                sink_guard.clear();
-                                set_raw_ui_state(&ui);
-                                log::warn!("song list is empty, reset UI state");
+                            let mut song_list: Vec<_> = ui_state.get_song_list().iter().collect();
+                            if song_list.is_empty() {
+                                log::warn!("song list is empty, can't sort");
+                                return;
+                            }
+                            utils::sort_songs(&mut song_list, key, ascending);
+                            set_song_list(&ui_state, song_list);
+                            // 搜索过滤后当前歌曲可能不在可见列表中
+                            if let Some(new_cur_song) = ui_state
+                                .get_song_list()
+                                .iter()
+                                .find(|x| x.song_path == ui_state.get_current_song().song_path)
+                            {
+                                ui_state.set_current_song(new_cur_song);
                             }
+                            ui_state.set_sort_key(key);
+                            ui_state.set_sort_ascending(ascending);
+                            ui_state.set_last_sort_key(key);
+                            log::info!("song list sorted by <{:?}>, ascending: {}", key, ascending);
+                        }
+                    })
+                    .unwrap();
+                }
+                PlayerCommand::StartRadio(seed) => {
+                    let ui_weak = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        if let Some(ui) = ui_weak.upgrade() {
+                            let ui_state = ui.global::<UIState>();
+                            ui_state.set_radio_seed(seed.clone());
+                            ui_state.set_play_mode(PlayMode::Radio);
+                            log::info!("artist radio started from <{}>", seed.singer);
+                            ui.invoke_play(seed, TriggerSource::ClickItem);
+                        }
+                    })
+                    .unwrap();
+                }
+                PlayerCommand::EnqueueNext(song) => {
+                    let ui_weak = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        if let Some(ui) = ui_weak.upgrade() {
+                            let ui_state = ui.global::<UIState>();
+                            let mut queue = ui_state.get_queue().iter().collect::<Vec<_>>();
+                            // 插在已播放部分之后，下一次切歌时优先播放
+                            let idx = (ui_state.get_queue_index() as usize).min(queue.len());
+                            log::info!("queue next: <{}>", song.song_name);
+                            queue.insert(idx, song);
+                            ui_state.set_queue(queue.as_slice().into());
+                        }
+                    })
+                    .unwrap();
+                }
+                PlayerCommand::EnqueueLast(song) => {
+                    let ui_weak = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        if let Some(ui) = ui_weak.upgrade() {
+                            let ui_state = ui.global::<UIState>();
+                            let mut queue = ui_state.get_queue().iter().collect::<Vec<_>>();
+                            log::info!("add to queue: <{}>", song.song_name);
+                            queue.push(song);
+                            ui_state.set_queue(queue.as_slice().into());
+                        }
+                    })
+                    .unwrap();
+                }
+                PlayerCommand::ToggleFavorite(song) => {
+                    let favorite = {
+                        let mut overrides = overrides_clone.lock().unwrap();
+                        let favorite = overrides.toggle_favorite(&song.song_path);
+                        overrides.save();
+                        favorite
+                    };
+                    log::info!("favorite <{}>: {}", song.song_name, favorite);
+                    let ui_weak = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        if let Some(ui) = ui_weak.upgrade() {
+                            let ui_state = ui.global::<UIState>();
+                            update_song(&ui_state, &song.song_path, |x| x.favorite = favorite);
+                            // 只显示收藏时，取消收藏的歌曲要从列表中移除
+                            if ui_state.get_favorites_only() {
+                                apply_song_filter(&ui_state);
+                            }
+                        }
+                    })
+                    .unwrap();
+                }
+                PlayerCommand::UpdateMetadata(song) => {
+                    let path = song.song_path.to_string();
+                    // 写入后重新读取，列表显示的是文件中实际保存的标签
+                    let result = utils::write_meta_info(&path, &song).and_then(|_| {
+                        utils::read_meta_info(&path)
+                            .ok_or_else(|| std::io::Error::other("can't read tags back"))
+                    });
+                    let ui_weak = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        if let Some(ui) = ui_weak.upgrade() {
+                            let ui_state = ui.global::<UIState>();
+                            match result {
+                                Ok(saved) => {
+                                    log::info!("updated tags of <{}>", path);
+                                    ui_state.set_tag_error(SharedString::new());
+                                    update_song(&ui_state, &path, |x| {
+                                        x.song_name = saved.song_name.clone();
+                                        x.singer = saved.singer.clone();
+                                        x.album = saved.album.clone();
+                                    });
+                                    // 新的标签可能影响搜索结果和排序
+                                    apply_song_filter(&ui_state);
+                                }
+                                Err(e) => {
+                                    log::error!("failed to write tags of <{}>: {}", path, e);
+                                    ui_state.set_tag_error(e.to_shared_string());
+                                }
+                            }
+                        }
+                    })
+                    .unwrap();
+                }
+                PlayerCommand::SavePlaylist(path, songs) => {
+                    match utils::save_playlist(&path, &songs) {
+                        Ok(_) => log::info!("saved {} songs to playlist: {:?}", songs.len(), path),
+                        Err(e) => log::error!("failed to save playlist {:?}: <{}>", path, e),
+                    }
+                    let ui_weak = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        if let Some(ui) = ui_weak.upgrade() {
+                            ui.invoke_refresh_playlists();
+                        }
+                    })
+                    .unwrap();
+                }
+                PlayerCommand::OpenLog => {
+                    let path = logger::log_path();
+                    // 没有程序关联 .log 文件时，退而在文件管理器中打开所在文件夹
+                    if let Err(e) = open::that_detached(&path) {
+                        log::warn!("failed to open log file {:?}: {}, opening its folder", path, e);
+                        if let Some(dir) = path.parent()
+                            && let Err(e) = open::that_detached(dir)
+                        {
+                            log::error!("failed to open log folder {:?}: {}", dir, e);
+                        }
+                    }
+                }
+                PlayerCommand::LoadPlaylist(path) => {
+                    let songs = utils::load_playlist(&path).map(|mut songs| {
+                        overrides_clone.lock().unwrap().apply(&mut songs);
+                        songs
+                    });
+                    let ui_weak = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        if let Some(ui) = ui_weak.upgrade() {
+                            let ui_state = ui.global::<UIState>();
+                            let songs = match songs {
+                                Ok(songs) => songs,
+                                Err(e) => {
+                                    log::error!("failed to load playlist {:?}: <{}>", path, e);
+                                    ui_state.set_import_error(e.to_string().into());
+                                    return;
+                                }
+                            };
+                            log::info!("loaded {} songs from playlist {:?}", songs.len(), path);
+                            ui_state.set_import_error("".into());
+                            ui_state.set_import_matched(songs.len() as i32);
+                            ui_state.set_import_unmatched_count(0);
+                            ui_state.set_import_unmatched("".into());
+                            let Some(first_song) = songs.first().cloned() else {
+                                return;
+                            };
+                            // 第一首立即播放，算作队列中已播放的一项
+                            ui_state.set_queue(songs.as_slice().into());
+                            ui_state.set_queue_index(1);
+                            ui.invoke_play(first_song, TriggerSource::ClickItem);
                         }
                     })
                     .unwrap();
                 }
-                PlayerCommand::SortSongList(key, ascending) => {
+                PlayerCommand::ImportPlaylist(path) => {
+                    let entries = playlist::read_playlist(&path);
                     let ui_weak = ui_weak.clone();
                     slint::invoke_from_event_loop(move || {
                         if let Some(ui) = ui_weak.upgrade() {
                             let ui_state = ui.global::<UIState>();
-                            let mut song_list: Vec<_> = ui_state.get_song_list().iter().collect();
-                            if song_list.is_empty() {
-                                log::warn!("song list is empty, can't sort");
-                                return;
-                            }
-                            match key {
-                                SortKey::BySongName => {
-                                    if ascending {
-                                        song_list.par_sort_by_key(|a| a.song_name.clone());
-                                    } else {
-                                        song_list.par_sort_by_key(|a| Reverse(a.song_name.clone()));
-                                    }
-                                }
-                                SortKey::BySinger => {
-                                    if ascending {
-                                        song_list.par_sort_by_key(|a| a.singer.clone());
-                                    } else {
-                                        song_list.par_sort_by_key(|a| Reverse(a.singer.clone()));
-                                    }
-                                }
-                                SortKey::ByDuration => {
-                                    if ascending {
-                                        song_list.par_sort_by_key(|a| a.duration.clone());
-                                    } else {
-                                        song_list.par_sort_by_key(|a| Reverse(a.duration.clone()));
-                                    }
+                            let entries = match entries {
+                                Ok(entries) => entries,
+                                Err(e) => {
+                                    log::error!("failed to read playlist {:?}: <{}>", path, e);
+                                    ui_state.set_import_error(e.to_string().into());
+                                    return;
                                 }
+                            };
+                            let library = ui_state.get_library().iter().collect::<Vec<_>>();
+                            let (matched, unmatched) = playlist::match_entries(&entries, &library);
+                            for entry in unmatched.iter() {
+                                log::warn!("playlist entry not found in library: {:?}", entry);
                             }
-                            song_list.iter_mut().enumerate().for_each(|(i, x)| x.id = i as i32);
-                            let new_cur_song = song_list
+                            log::info!(
+                                "imported playlist {:?}: {} matched, {} unmatched",
+                                path,
+                                matched.len(),
+                                unmatched.len()
+                            );
+                            let mut queue = ui_state.get_queue().iter().collect::<Vec<_>>();
+                            queue.extend(matched.iter().cloned());
+                            ui_state.set_queue(queue.as_slice().into());
+                            let names = unmatched
                                 .iter()
-                                .find(|x| x.song_path == ui_state.get_current_song().song_path)
-                                .unwrap();
-                            ui_state.set_current_song(new_cur_song.clone());
-                            ui_state.set_sort_key(key);
-                            ui_state.set_sort_ascending(ascending);
-                            ui_state.set_last_sort_key(key);
-                            ui_state.set_song_list(song_list.as_slice().into());
-                            log::info!("song list sorted by <{:?}>, ascending: {}", key, ascending);
+                                .map(|x| match &x.title {
+                                    Some(title) => title.clone(),
+                                    None => x.path.rsplit(['/', '\\']).next().unwrap_or("").into(),
+                                })
+                                .collect::<Vec<_>>();
+                            ui_state.set_import_error("".into());
+                            ui_state.set_import_matched(matched.len() as i32);
+                            ui_state.set_import_unmatched_count(unmatched.len() as i32);
+                            ui_state.set_import_unmatched(names.join(", ").into());
                         }
                     })
                     .unwrap();
@@ -483,11 +2221,168 @@ fn main() {
                 .expect("failed to send change progress command");
         });
     }
+    {
+        let ui_weak = ui.as_weak();
+        ui.on_filter_song_list(move |query| {
+            if let Some(ui) = ui_weak.upgrade() {
+                let ui_state = ui.global::<UIState>();
+                ui_state.set_filter_query(query.clone());
+                apply_song_filter(&ui_state);
+                log::debug!("song list filtered by <{}>", query);
+            }
+        });
+    }
+    {
+        let ui_weak = ui.as_weak();
+        ui.on_set_song_grouping(move |grouping| {
+            if let Some(ui) = ui_weak.upgrade() {
+                let ui_state = ui.global::<UIState>();
+                ui_state.set_song_grouping(grouping);
+                apply_song_filter(&ui_state);
+                log::info!("song list grouped by <{:?}>", grouping);
+            }
+        });
+    }
+    {
+        let ui_weak = ui.as_weak();
+        ui.on_set_search_lyrics(move |enabled| {
+            let Some(ui) = ui_weak.upgrade() else {
+                return;
+            };
+            let ui_state = ui.global::<UIState>();
+            ui_state.set_search_lyrics(enabled);
+            apply_song_filter(&ui_state);
+            if enabled {
+                // 第一次开启时在后台读取曲库中所有歌曲的歌词，读完后重新筛选
+                utils::enable_lyrics_index();
+                let library = ui_state.get_library().iter().collect::<Vec<_>>();
+                let ui_weak = ui_weak.clone();
+                thread::spawn(move || {
+                    utils::index_lyrics(&library);
+                    slint::invoke_from_event_loop(move || {
+                        if let Some(ui) = ui_weak.upgrade() {
+                            let ui_state = ui.global::<UIState>();
+                            if ui_state.get_search_lyrics() {
+                                apply_song_filter(&ui_state);
+                            }
+                        }
+                    })
+                    .unwrap();
+                });
+            }
+        });
+    }
+    {
+        let ui_weak = ui.as_weak();
+        ui.on_select_folder(move |folder| {
+            if let Some(ui) = ui_weak.upgrade() {
+                let ui_state = ui.global::<UIState>();
+                ui_state.set_folder_filter(folder.clone());
+                apply_song_filter(&ui_state);
+                log::debug!("song list filtered by folder <{}>", folder);
+            }
+        });
+    }
+    {
+        let tx = tx.clone();
+        ui.on_set_sleep_timer(move |minutes, end_of_track| {
+            log::info!("request sleep timer: {} min, end of track: {}", minutes, end_of_track);
+            let after = (minutes > 0).then(|| Duration::from_secs(minutes as u64 * 60));
+            tx.send(PlayerCommand::SetSleepTimer(after))
+                .expect("failed to send set sleep timer command");
+        });
+    }
+    {
+        let tx = tx.clone();
+        ui.on_set_volume(move |volume: f32| {
+            log::debug!("request to set volume to: <{:.2}>", volume);
+            tx.send(PlayerCommand::SetVolume(volume)).expect("failed to send set volume command");
+        });
+    }
+    {
+        let tx = tx.clone();
+        ui.on_seek_relative(move |delta| {
+            tx.send(PlayerCommand::SeekRelative(delta)).expect("failed to send seek command");
+        });
+    }
+    {
+        let tx = tx.clone();
+        ui.on_set_crossfade(move |secs| {
+            tx.send(PlayerCommand::SetCrossfade(secs))
+                .expect("failed to send set crossfade command");
+        });
+    }
+    {
+        let tx = tx.clone();
+        let ui_weak = ui.as_weak();
+        ui.on_set_eq_band(move |band, db| {
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.global::<UIState>().get_eq_gains().set_row_data(band as usize, db);
+            }
+            tx.send(PlayerCommand::SetEqBand(band as usize, db))
+                .expect("failed to send set eq band command");
+        });
+    }
+    {
+        let ui_weak = ui.as_weak();
+        ui.on_set_compact(move |compact| {
+            if let Some(ui) = ui_weak.upgrade() {
+                set_compact(&ui, compact);
+            }
+        });
+    }
+    {
+        let tx = tx.clone();
+        ui.on_open_log(move || {
+            log::info!("request to open log file");
+            tx.send(PlayerCommand::OpenLog).expect("failed to send open log command");
+        });
+    }
+    {
+        let tx = tx.clone();
+        ui.on_play_url(move |url| {
+            log::info!("request to play url: <{}>", url);
+            tx.send(PlayerCommand::PlayUrl(url.trim().into()))
+                .expect("failed to send play url command");
+        });
+    }
+    {
+        let tx = tx.clone();
+        ui.on_set_balance(move |balance| {
+            tx.send(PlayerCommand::SetBalance(balance))
+                .expect("failed to send set balance command");
+        });
+    }
+    {
+        let tx = tx.clone();
+        ui.on_set_eq_enabled(move |enabled| {
+            tx.send(PlayerCommand::SetEqEnabled(enabled))
+                .expect("failed to send set eq enabled command");
+        });
+    }
+    {
+        let tx = tx.clone();
+        let ui_weak = ui.as_weak();
+        ui.on_apply_eq_preset(move |index| {
+            let Some(gains) = dsp::EQ_PRESETS.get(index as usize) else {
+                return;
+            };
+            log::info!("applying equalizer preset {}", index);
+            for (band, db) in gains.iter().enumerate() {
+                tx.send(PlayerCommand::SetEqBand(band, *db))
+                    .expect("failed to send set eq band command");
+            }
+            if let Some(ui) = ui_weak.upgrade() {
+                set_eq_ui_state(&ui, ui.global::<UIState>().get_eq_enabled(), *gains);
+            }
+        });
+    }
     {
         let tx = tx.clone();
         ui.on_play_next(move || {
             log::info!("request to play next");
-            tx.send(PlayerCommand::PlayNext).expect("failed to send play next command");
+            tx.send(PlayerCommand::PlayNext(TriggerSource::Next))
+                .expect("failed to send play next command");
         });
     }
     {
@@ -507,10 +2402,62 @@ fn main() {
     }
     {
         let tx = tx.clone();
-        ui.on_refresh_song_list(move |path| {
-            log::info!("request to refresh song list from: {:?}", path);
-            tx.send(PlayerCommand::RefreshSongList(path.as_str().into()))
-                .expect("failed to send refresh song list command");
+        let ui_weak = ui.as_weak();
+        ui.on_refresh_song_list(move |full| {
+            if let Some(ui) = ui_weak.upgrade() {
+                let dirs = song_dirs(&ui.global::<UIState>());
+                log::info!("request to refresh song list from: {:?}, full rescan: {}", dirs, full);
+                tx.send(PlayerCommand::RefreshSongList(dirs, full))
+                    .expect("failed to send refresh song list command");
+            }
+        });
+    }
+    // 目录监视器，音乐目录改变后重新创建；部分网络驱动器不支持监视，可在配置中关闭
+    let dir_watcher = Rc::new(RefCell::new(None));
+    if cfg.watch_dirs {
+        watch_song_dirs(&ui, &dir_watcher, &cfg.song_dirs);
+    }
+    {
+        let ui_weak = ui.as_weak();
+        let dir_watcher = dir_watcher.clone();
+        let watch_dirs = cfg.watch_dirs;
+        ui.on_add_song_dir(move |dir| {
+            if let Some(ui) = ui_weak.upgrade() {
+                let ui_state = ui.global::<UIState>();
+                let dir = PathBuf::from(dir.trim());
+                let mut dirs = song_dirs(&ui_state);
+                if dir.as_os_str().is_empty() || dirs.contains(&dir) {
+                    return;
+                }
+                log::info!("add music directory: {:?}", dir);
+                dirs.push(dir);
+                ui_state.set_song_dirs(path_list(&dirs));
+                if watch_dirs {
+                    watch_song_dirs(&ui, &dir_watcher, &dirs);
+                }
+                ui.invoke_refresh_song_list(false);
+            }
+        });
+    }
+    {
+        let ui_weak = ui.as_weak();
+        let dir_watcher = dir_watcher.clone();
+        let watch_dirs = cfg.watch_dirs;
+        ui.on_remove_song_dir(move |index| {
+            if let Some(ui) = ui_weak.upgrade() {
+                let ui_state = ui.global::<UIState>();
+                let mut dirs = song_dirs(&ui_state);
+                // 至少保留一个目录
+                if dirs.len() <= 1 || index as usize >= dirs.len() {
+                    return;
+                }
+                log::info!("remove music directory: {:?}", dirs.remove(index as usize));
+                ui_state.set_song_dirs(path_list(&dirs));
+                if watch_dirs {
+                    watch_song_dirs(&ui, &dir_watcher, &dirs);
+                }
+                ui.invoke_refresh_song_list(false);
+            }
         });
     }
     {
@@ -529,41 +2476,468 @@ fn main() {
                 .expect("failed to send set language command");
         });
     }
+    {
+        let tx = tx.clone();
+        ui.on_start_radio(move |seed| {
+            log::info!("request to start radio from: <{}>", seed.song_name);
+            tx.send(PlayerCommand::StartRadio(seed)).expect("failed to send start radio command");
+        });
+    }
+    {
+        let dsp_controls = dsp_controls.clone();
+        ui.on_set_channel_mode(move |mode| {
+            log::info!("channel mode set to <{:?}>", mode);
+            dsp_controls.set_channel_mode(mode);
+        });
+    }
+    {
+        let dsp_controls = dsp_controls.clone();
+        ui.on_set_preamp(move |db| {
+            log::debug!("preamp set to {:.1} dB", db);
+            dsp_controls.set_preamp_db(db);
+        });
+    }
+    {
+        // 频谱只在歌词页可见时计算，隐藏时停止采样和定时器，不占用 CPU
+        let ui_weak = ui.as_weak();
+        let dsp_controls = dsp_controls.clone();
+        let spectrum_timer = Rc::new(slint::Timer::default());
+        let analyzer = Rc::new(SpectrumAnalyzer::new(dsp::SPECTRUM_WINDOW));
+        ui.on_set_spectrum_active(move |active| {
+            log::debug!("spectrum visualizer active: {}", active);
+            dsp_controls.set_spectrum_enabled(active);
+            if !active {
+                spectrum_timer.stop();
+                if let Some(ui) = ui_weak.upgrade() {
+                    ui.global::<UIState>().set_spectrum(Default::default());
+                }
+                return;
+            }
+            let ui_weak = ui_weak.clone();
+            let dsp_controls = dsp_controls.clone();
+            let analyzer = analyzer.clone();
+            let mut levels = vec![0.; SPECTRUM_BARS];
+            spectrum_timer.start(slint::TimerMode::Repeated, SPECTRUM_FRAME, move || {
+                if let Some(ui) = ui_weak.upgrade() {
+                    let ui_state = ui.global::<UIState>();
+                    // 暂停时缓冲区不再更新，让柱子逐渐落下
+                    let bars = match dsp_controls.spectrum_window() {
+                        Some((samples, sample_rate)) if !ui_state.get_paused() => {
+                            analyzer.bars(&samples, sample_rate)
+                        }
+                        _ => vec![0.; SPECTRUM_BARS],
+                    };
+                    for (level, bar) in levels.iter_mut().zip(bars) {
+                        *level = bar.max(*level * SPECTRUM_FALLOFF);
+                    }
+                    ui_state.set_spectrum(levels.as_slice().into());
+                }
+            });
+        });
+    }
+    {
+        let ui_weak = ui.as_weak();
+        ui.on_refresh_output_devices(move || {
+            if let Some(ui) = ui_weak.upgrade() {
+                let mut devices = vec![SharedString::new()];
+                devices.extend(output::list_output_devices().into_iter().map(SharedString::from));
+                log::debug!("output devices: {:?}", devices);
+                ui.global::<UIState>().set_output_devices(devices.as_slice().into());
+            }
+        });
+    }
+    {
+        let ui_weak = ui.as_weak();
+        let stream_handle = stream_handle.clone();
+        let mixer = mixer.clone();
+        let sink = sink.clone();
+        let fading = fading.clone();
+        let replay_gain = cfg.replay_gain;
+        let dsp_controls = dsp_controls.clone();
+        let prev_device = RefCell::new(cfg.output_device.clone());
+        ui.on_select_output_device(move |name| {
+            if let Some(ui) = ui_weak.upgrade() {
+                let ui_state = ui.global::<UIState>();
+                ui_state.set_device_lost(SharedString::new());
+                // 淡出中的上一首不随设备迁移
+                stop_fading(&fading);
+                match switch_output_device(
+                    &ui,
+                    &name,
+                    &stream_handle,
+                    &mixer,
+                    &sink,
+                    replay_gain,
+                    &dsp_controls,
+                ) {
+                    Ok(_) => {
+                        log::info!("switched output device to <{}>", name);
+                        prev_device.replace(name.into());
+                        ui_state.set_device_error("".into());
+                    }
+                    Err(e) => {
+                        // 切换失败时继续使用之前的设备
+                        log::error!("failed to switch output device to <{}>: {}", name, e);
+                        ui_state.set_output_device(prev_device.borrow().as_str().into());
+                        ui_state.set_device_error(format!("{}: {}", name, e).into());
+                    }
+                }
+            }
+        });
+    }
+    {
+        // 输出设备变化时（如拔出 USB 声卡、切换到耳机）自动重建输出流，继续当前歌曲
+        let ui_weak = ui.as_weak();
+        let tx = tx.clone();
+        let pause_on_unplug = cfg.pause_on_unplug;
+        output::watch_output_devices(move |before, after| {
+            let (before, after) = (before.clone(), after.clone());
+            let ui_weak = ui_weak.clone();
+            let tx = tx.clone();
+            // 退出时事件循环已结束，忽略最后一次通知
+            let _ = slint::invoke_from_event_loop(move || {
+                if let Some(ui) = ui_weak.upgrade() {
+                    follow_output_devices(&ui, &before, &after, pause_on_unplug, &tx);
+                }
+            });
+        });
+    }
+    if cfg.focus_loss != FocusLossAction::Off {
+        // 其他应用占用音频焦点（如来电）时暂停或降低音量，焦点恢复后还原
+        let ui_weak = ui.as_weak();
+        let tx = tx.clone();
+        let action = cfg.focus_loss;
+        let acted = Arc::new(AtomicBool::new(false));
+        audio_focus::watch(move |lost| {
+            let ui_weak = ui_weak.clone();
+            let tx = tx.clone();
+            let acted = acted.clone();
+            // 退出时事件循环已结束，忽略最后一次通知
+            let _ = slint::invoke_from_event_loop(move || {
+                if let Some(ui) = ui_weak.upgrade() {
+                    follow_audio_focus(&ui, lost, action, &acted, &tx);
+                }
+            });
+        });
+    }
+    {
+        let ui_weak = ui.as_weak();
+        ui.on_remove_from_queue(move |idx| {
+            if let Some(ui) = ui_weak.upgrade() {
+                let ui_state = ui.global::<UIState>();
+                let mut queue = ui_state.get_queue().iter().collect::<Vec<_>>();
+                if idx < 0 || idx as usize >= queue.len() {
+                    return;
+                }
+                let song = queue.remove(idx as usize);
+                log::info!("remove from queue: <{}>", song.song_name);
+                // 移除已播放的项时，已播放计数随之减一
+                if idx < ui_state.get_queue_index() {
+                    ui_state.set_queue_index(ui_state.get_queue_index() - 1);
+                }
+                ui_state.set_queue(queue.as_slice().into());
+            }
+        });
+    }
+    {
+        let tx = tx.clone();
+        ui.on_add_to_queue(move |song| {
+            tx.send(PlayerCommand::EnqueueLast(song)).expect("failed to send enqueue command");
+        });
+    }
+    {
+        let tx = tx.clone();
+        ui.on_play_next_in_queue(move |song| {
+            tx.send(PlayerCommand::EnqueueNext(song)).expect("failed to send enqueue command");
+        });
+    }
+    {
+        let ui_weak = ui.as_weak();
+        let tx = tx.clone();
+        let album_resume = album_resume.clone();
+        ui.on_resume_album(move |song| {
+            let Some(ui) = ui_weak.upgrade() else {
+                return;
+            };
+            let point = album_resume.lock().unwrap().get(&song.song_path).cloned();
+            let library = ui.global::<UIState>().get_library();
+            let resumed = point.as_ref().and_then(|point| {
+                library.iter().find(|x| x.song_path == point.song_path).map(|x| (x, point.progress))
+            });
+            match resumed {
+                Some((target, progress)) => {
+                    log::info!("resume album at <{}> {:.1}s", target.song_name, progress);
+                    ui.invoke_play(target, TriggerSource::ClickItem);
+                    if progress > 0. {
+                        tx.send(PlayerCommand::ChangeProgress(progress))
+                            .expect("failed to send change progress command");
+                    }
+                }
+                None => {
+                    log::info!("no resume point for album of <{}>, play it", song.song_name);
+                    ui.invoke_play(song, TriggerSource::ClickItem);
+                }
+            }
+        });
+    }
+    {
+        let tx = tx.clone();
+        ui.on_toggle_favorite(move |song| {
+            tx.send(PlayerCommand::ToggleFavorite(song))
+                .expect("failed to send toggle favorite command");
+        });
+    }
+    {
+        let tx = tx.clone();
+        ui.on_update_metadata(move |song| {
+            tx.send(PlayerCommand::UpdateMetadata(song))
+                .expect("failed to send update metadata command");
+        });
+    }
+    {
+        let ui_weak = ui.as_weak();
+        ui.on_identify_track(move |song| {
+            if let Some(ui) = ui_weak.upgrade() {
+                let ui_state = ui.global::<UIState>();
+                ui_state.set_identify_path(song.song_path.clone());
+                ui_state.set_identifying(true);
+                ui_state.set_identify_error(SharedString::new());
+                ui_state.set_tag_suggestions(Default::default());
+            }
+            // 网络查找可能需要几秒，不阻塞 UI 和播放线程
+            let ui_weak = ui_weak.clone();
+            thread::spawn(move || {
+                let result = identify::lookup(&song);
+                slint::invoke_from_event_loop(move || {
+                    if let Some(ui) = ui_weak.upgrade() {
+                        let ui_state = ui.global::<UIState>();
+                        // 查找期间已开始查找别的歌曲，过时的结果直接丢弃
+                        if ui_state.get_identify_path() != song.song_path {
+                            log::info!("dropping stale tag suggestions for <{}>", song.song_path);
+                            return;
+                        }
+                        ui_state.set_identifying(false);
+                        match result {
+                            Ok(suggestions) => {
+                                log::info!(
+                                    "{} tag suggestions for <{}>",
+                                    suggestions.len(),
+                                    song.song_path
+                                );
+                                ui_state.set_tag_suggestions(suggestions.as_slice().into());
+                            }
+                            Err(e) => {
+                                log::warn!("failed to identify <{}>: {}", song.song_path, e);
+                                ui_state.set_identify_error(e.into());
+                            }
+                        }
+                    }
+                })
+                .unwrap();
+            });
+        });
+    }
+    {
+        let ui_weak = ui.as_weak();
+        let overrides = overrides.clone();
+        ui.on_toggle_skip_autoplay(move |song| {
+            let mut overrides = overrides.lock().unwrap();
+            let skip = overrides.toggle_skip_autoplay(&song.song_path);
+            overrides.save();
+            log::info!("skip <{}> in autoplay: {}", song.song_name, skip);
+            if let Some(ui) = ui_weak.upgrade() {
+                update_song(&ui.global::<UIState>(), &song.song_path, |x| x.skip_autoplay = skip);
+            }
+        });
+    }
+    {
+        let ui_weak = ui.as_weak();
+        ui.on_clear_queue(move || {
+            if let Some(ui) = ui_weak.upgrade() {
+                let ui_state = ui.global::<UIState>();
+                ui_state.set_queue(Vec::new().as_slice().into());
+                ui_state.set_queue_index(0);
+                log::info!("queue cleared");
+            }
+        });
+    }
+    {
+        let tx = tx.clone();
+        let ui_weak = ui.as_weak();
+        ui.on_save_history_playlist(move |name| {
+            let Some(path) = named_playlist_path(&name) else {
+                return;
+            };
+            let Some(ui) = ui_weak.upgrade() else {
+                return;
+            };
+            let history = ui.global::<UIState>().get_play_history().iter().collect::<Vec<_>>();
+            log::info!("request to save play history as playlist: {:?}", path);
+            tx.send(PlayerCommand::SavePlaylist(path, utils::dedup_history(&history)))
+                .expect("failed to send save playlist command");
+        });
+    }
+    {
+        let tx = tx.clone();
+        let ui_weak = ui.as_weak();
+        ui.on_save_queue_playlist(move |name| {
+            let Some(path) = named_playlist_path(&name) else {
+                return;
+            };
+            let Some(ui) = ui_weak.upgrade() else {
+                return;
+            };
+            let queue = ui.global::<UIState>().get_queue().iter().collect::<Vec<_>>();
+            log::info!("request to save queue as playlist: {:?}", path);
+            tx.send(PlayerCommand::SavePlaylist(path, queue))
+                .expect("failed to send save playlist command");
+        });
+    }
+    {
+        let tx = tx.clone();
+        ui.on_load_playlist(move |name| {
+            // 外部放入歌单目录的 .m3u 也可以加载
+            let dir = config::get_playlist_dir();
+            let path = ["m3u8", "m3u"]
+                .iter()
+                .map(|ext| dir.join(format!("{}.{}", name, ext)))
+                .find(|x| x.exists());
+            match path {
+                Some(path) => {
+                    log::info!("request to load playlist: {:?}", path);
+                    tx.send(PlayerCommand::LoadPlaylist(path))
+                        .expect("failed to send load playlist command");
+                }
+                None => log::warn!("playlist <{}> not found in {:?}", name, dir),
+            }
+        });
+    }
+    {
+        let ui_weak = ui.as_weak();
+        ui.on_refresh_playlists(move || {
+            if let Some(ui) = ui_weak.upgrade() {
+                let names = utils::list_playlists(config::get_playlist_dir());
+                let names = names.into_iter().map(SharedString::from).collect::<Vec<_>>();
+                ui.global::<UIState>().set_playlists(names.as_slice().into());
+            }
+        });
+    }
+    {
+        let tx = tx.clone();
+        ui.on_import_playlist(move |path| {
+            log::info!("request to import playlist: <{}>", path);
+            tx.send(PlayerCommand::ImportPlaylist(path.trim().into()))
+                .expect("failed to send import playlist command");
+        });
+    }
     // pure callback to format duration string
-    ui.on_format_duration(|dura| {
-        format!("{:02}:{:02}", (dura as u32) / 60, (dura as u32) % 60).to_shared_string()
+    ui.on_format_duration(|dura, show_ms| {
+        if show_ms {
+            let ms = (dura.max(0.) * 1000.).round() as u64;
+            format!("{:02}:{:02}.{:03}", ms / 60_000, ms / 1000 % 60, ms % 1000).to_shared_string()
+        } else {
+            format!("{:02}:{:02}", (dura as u32) / 60, (dura as u32) % 60).to_shared_string()
+        }
     });
     // UI 定时刷新进度条
     let ui_weak = ui.as_weak();
     let timer = slint::Timer::default();
     let sink_clone = sink.clone();
+    let tx_clone = tx.clone();
+    let mut crossfade_armed = true;
     timer.start(slint::TimerMode::Repeated, Duration::from_millis(200), move || {
         let sink_guard = sink_clone.lock().unwrap();
         if let Some(ui) = ui_weak.upgrade() {
             // 如果不在拖动进度条，则自增进度条
             let ui_state = ui.global::<UIState>();
+            // 暂停状态可能来自多处（按钮、媒体键、睡眠定时），统一在这里同步给系统媒体控件
+            media_session::set_paused(ui_state.get_paused());
+            discord::sync(ui_state.get_paused(), ui_state.get_progress());
+            tray::set_paused(&ui_state.get_current_song(), ui_state.get_paused());
+            // 睡眠定时到期：暂停，并且不再自动播放下一首
+            let mut deadline = sleep_deadline.lock().unwrap();
+            if let Some(at) = *deadline {
+                let now = Instant::now();
+                if now >= at {
+                    *deadline = None;
+                    sink_guard.pause();
+                    ui_state.set_paused(true);
+                    ui_state.set_user_listening(false);
+                    ui_state.set_sleep_timer_minutes(0);
+                    log::info!("sleep timer fired, playback paused");
+                } else {
+                    ui_state.set_sleep_remaining((at - now).as_secs().div_ceil(60) as i32);
+                }
+            }
+            drop(deadline);
             if !ui_state.get_dragging() {
                 ui_state.set_progress(sink_guard.get_pos().as_secs_f32());
+                // A-B 循环：越过 B 点后跳回 A 点
+                let (a, b) = (ui_state.get_loop_a(), ui_state.get_loop_b());
+                let progress = ui_state.get_progress();
+                if a >= 0. && b > a && progress >= b {
+                    ui.invoke_change_progress(a);
+                    log::debug!("a-b loop jumped back to <{}>", a);
+                } else if a >= 0. && b > a && b - progress < 0.2 && !ui_state.get_paused() {
+                    // B 点落在两次刷新之间时，用单次定时器精确跳回，而不是等到下一次刷新
+                    let ui_weak = ui.as_weak();
+                    slint::Timer::single_shot(Duration::from_secs_f32(b - progress), move || {
+                        if let Some(ui) = ui_weak.upgrade() {
+                            let ui_state = ui.global::<UIState>();
+                            if ui_state.get_loop_a() == a && ui_state.get_loop_b() == b {
+                                ui.invoke_change_progress(a);
+                                log::debug!("a-b loop jumped back to <{}>", a);
+                            }
+                        }
+                    });
+                }
             }
             if !ui_state.get_paused() {
                 for (idx, item) in ui_state.get_lyrics().iter().enumerate() {
                     let delta = item.time - ui_state.get_progress();
                     if delta < 0. && delta > -0.20 {
-                        if idx <= 5 {
-                            ui_state.set_lyric_viewport_y(0.)
-                        } else {
-                            ui_state.set_lyric_viewport_y(
-                                (5_f32 - idx as f32) * ui_state.get_lyric_line_height(),
-                            );
-                        }
+                        // 滚动位置由歌词面板按自身高度计算，窗口缩放时也会重新居中
+                        ui_state.set_current_lyric(idx as i32);
                         log::debug!("lyric changed to: <{:?}>", item);
                         break;
                     }
                 }
             }
-            // 如果播放完毕，且之前是在播放状态，则自动播放下一首
-            if sink_guard.empty() && ui_state.get_user_listening() && !ui_state.get_paused() {
-                ui.invoke_play_next();
+            // 开启交叉淡入淡出时，在歌曲结束前提前切换到下一首，每首歌只触发一次
+            let crossfade_secs = ui_state.get_crossfade_secs();
+            let remaining = ui_state.get_duration() - ui_state.get_progress();
+            if remaining > crossfade_secs {
+                crossfade_armed = true;
+            } else if crossfade_armed
+                && crossfade_secs > 0.
+                && ui_state.get_duration() > crossfade_secs * 2.
+                && ui_state.get_loop_b() < 0.
+                && !ui_state.get_sleep_at_track_end()
+                && ui_state.get_user_listening()
+                && !ui_state.get_paused()
+                && !ui_state.get_dragging()
+            {
+                crossfade_armed = false;
+                tx_clone
+                    .send(PlayerCommand::PlayNext(TriggerSource::Auto))
+                    .expect("failed to send play next command");
+                log::info!("song ending, crossfade to next");
+            }
+            // 如果播放完毕，且之前是在播放状态，则自动播放下一首；设置了“播完当前歌曲后停止”则停下
+            if sink_guard.empty()
+                && ui_state.get_user_listening()
+                && !ui_state.get_paused()
+                && ui_state.get_sleep_at_track_end()
+            {
+                ui_state.set_sleep_at_track_end(false);
+                ui_state.set_paused(true);
+                ui_state.set_user_listening(false);
+                log::info!("song ended, stopped by sleep timer");
+            } else if sink_guard.empty() && ui_state.get_user_listening() && !ui_state.get_paused()
+            {
+                tx_clone
+                    .send(PlayerCommand::PlayNext(TriggerSource::Auto))
+                    .expect("failed to send play next command");
                 log::info!("song ended, auto play next");
             }
         }
@@ -571,22 +2945,98 @@ fn main() {
 
     // 显示 UI
     log::info!("ui state initialized, take: {:?}", app_start.elapsed());
-    ui.run().expect("failed to run UI");
+    // 恢复上次的窗口大小和位置
+    if cfg.window_width > 0 && cfg.window_height > 0 {
+        ui.window().set_size(slint::PhysicalSize::new(cfg.window_width, cfg.window_height));
+    }
+    if cfg.compact {
+        set_compact(&ui, true);
+    }
+    ui.show().expect("failed to show UI");
+    if cfg.window_width > 0 {
+        restore_window_position(&ui, cfg.window_x, cfg.window_y);
+    }
+    // 系统媒体控件在 Windows 上需要绑定已创建的窗口
+    media_session::init(&ui, tx.clone());
+    let ui_state = ui.global::<UIState>();
+    let cur_song = ui_state.get_current_song();
+    let cover_file = cover_cache.lock().unwrap().thumbnail_file(&cur_song.song_path);
+    media_session::set_song(&cur_song, ui_state.get_duration(), cover_file.as_deref());
+    discord::init(&cfg.discord);
+    discord::set_song(&cur_song, ui_state.get_duration());
+    if cfg.tray_icon {
+        // macOS 上托盘图标要在事件循环运行之后创建
+        let ui_weak = ui.as_weak();
+        let tx = tx.clone();
+        slint::Timer::single_shot(Duration::ZERO, move || {
+            if let Some(ui) = ui_weak.upgrade() {
+                tray::init(&ui, tx);
+                tray::set_song(&ui.global::<UIState>().get_current_song());
+            }
+        });
+    }
+    // 开启关闭到托盘时，关闭窗口只是隐藏，从托盘菜单退出；托盘不可用时照常退出
+    let close_to_tray = cfg.close_to_tray;
+    ui.window().on_close_requested(move || {
+        if !(close_to_tray && tray::is_active()) {
+            let _ = slint::quit_event_loop();
+        }
+        slint::CloseRequestResponse::HideWindow
+    });
+    if let Some(path) = cli_file() {
+        log::info!("open file from command line: {:?}", path);
+        open_external_file(&ui, &path, &overrides.lock().unwrap());
+    }
+    {
+        let ui_weak = ui.as_weak();
+        let overrides = overrides.clone();
+        ipc::listen(move |path| {
+            let ui_weak = ui_weak.clone();
+            let overrides = overrides.clone();
+            slint::invoke_from_event_loop(move || {
+                if let Some(ui) = ui_weak.upgrade() {
+                    ui.window().set_minimized(false);
+                    ui.show().expect("failed to show UI");
+                    if let Some(path) = path {
+                        log::info!("open file from another instance: {:?}", path);
+                        open_external_file(&ui, &path, &overrides.lock().unwrap());
+                    }
+                }
+            })
+            .unwrap();
+        });
+    }
+    // 定期自动保存配置，程序崩溃或被强制结束时不会丢失状态；内容没有变化时不写文件
+    let autosave_timer = slint::Timer::default();
+    {
+        let ui_weak = ui.as_weak();
+        let base = cfg.clone();
+        let mut last_saved = cfg.clone();
+        autosave_timer.start(slint::TimerMode::Repeated, AUTOSAVE_INTERVAL, move || {
+            if let Some(ui) = ui_weak.upgrade() {
+                let cfg = current_config(&ui, &base);
+                if cfg != last_saved {
+                    log::debug!("autosaving config");
+                    cfg.clone().save();
+                    last_saved = cfg;
+                }
+            }
+        });
+    }
+    slint::run_event_loop_until_quit().expect("failed to run UI");
+    discord::shutdown();
 
     // 退出前保存状态
     log::info!("saving config...");
     let ui_state = ui.global::<UIState>();
-    Config::save({
-        Config {
-            song_dir: ui_state.get_song_dir().as_str().into(),
-            current_song_path: Some(ui_state.get_current_song().song_path.as_str().into()),
-            progress: ui_state.get_progress(),
-            play_mode: ui_state.get_play_mode(),
-            sort_key: ui_state.get_sort_key(),
-            sort_ascending: ui_state.get_sort_ascending(),
-            lang: ui_state.get_lang().into(),
-            light_ui: ui_state.get_light_ui(),
-        }
-    });
+    let mut album_resume = album_resume.lock().unwrap();
+    album_resume.record(
+        &ui_state.get_current_song().song_path,
+        ui_state.get_progress(),
+        ui_state.get_duration(),
+    );
+    album_resume.save();
+    PlayHistory::from_songs(&ui_state.get_play_history().iter().collect::<Vec<_>>()).save();
+    current_config(&ui, &cfg).save();
     log::info!("app exited");
 }
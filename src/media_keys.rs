@@ -0,0 +1,40 @@
+use std::sync::mpsc::Sender;
+
+use global_hotkey::{
+    GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState,
+    hotkey::{Code, HotKey},
+};
+
+use crate::{PlayerCommand, slint_types::TriggerSource};
+
+/// Register the play/pause, next and previous media keys system wide, forwarding them to the player thread.
+/// Returns the manager that keeps the keys registered while alive, or `None` if the platform refuses them.
+pub fn register(tx: Sender<PlayerCommand>) -> Option<GlobalHotKeyManager> {
+    let manager = GlobalHotKeyManager::new()
+        .inspect_err(|e| log::warn!("global media keys unavailable: {}", e))
+        .ok()?;
+    let play_pause = HotKey::new(None, Code::MediaPlayPause);
+    let next = HotKey::new(None, Code::MediaTrackNext);
+    let prev = HotKey::new(None, Code::MediaTrackPrevious);
+    if let Err(e) = manager.register_all(&[play_pause, next, prev]) {
+        log::warn!("failed to register global media keys: {}", e);
+        return None;
+    }
+    GlobalHotKeyEvent::set_event_handler(Some(move |event: GlobalHotKeyEvent| {
+        if event.state() != HotKeyState::Pressed {
+            return;
+        }
+        let cmd = match event.id() {
+            id if id == play_pause.id() => PlayerCommand::Pause,
+            id if id == next.id() => PlayerCommand::PlayNext(TriggerSource::Next),
+            id if id == prev.id() => PlayerCommand::PlayPrev,
+            _ => return,
+        };
+        log::info!("media key pressed: {}", cmd.name());
+        if tx.send(cmd).is_err() {
+            log::warn!("player thread is gone, media key ignored");
+        }
+    }));
+    log::info!("global media keys registered");
+    Some(manager)
+}
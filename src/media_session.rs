@@ -0,0 +1,143 @@
+use std::{
+    cell::RefCell,
+    ffi::c_void,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+    },
+    time::Duration,
+};
+
+use souvlaki::{
+    MediaControlEvent, MediaControls, MediaMetadata, MediaPlayback, MediaPosition, PlatformConfig,
+};
+
+use crate::{
+    PlayerCommand,
+    slint_types::{MainWindow, SongInfo, TriggerSource},
+};
+
+/// Now-playing info published to the OS media overlay:
+/// SMTC on Windows, MPRIS on Linux, Now Playing on macOS
+struct MediaSession {
+    controls: MediaControls,
+    /// Pause state last published, read by the OS button handler to map play/pause to a toggle
+    paused: Arc<AtomicBool>,
+}
+
+thread_local! {
+    // 系统媒体控件只在 UI 线程中创建和更新
+    static SESSION: RefCell<Option<MediaSession>> = const { RefCell::new(None) };
+}
+
+/// Register with the OS media controls, routing its buttons into the player channel.
+/// Must be called on the UI thread once the window is shown, failures only log a warning.
+pub fn init(ui: &MainWindow, tx: Sender<PlayerCommand>) {
+    let config = PlatformConfig {
+        display_name: "Zeedle",
+        dbus_name: "zeedle",
+        hwnd: window_hwnd(ui),
+    };
+    let mut controls = match MediaControls::new(config) {
+        Ok(controls) => controls,
+        Err(e) => {
+            log::warn!("system media controls unavailable: {:?}", e);
+            return;
+        }
+    };
+    let paused = Arc::new(AtomicBool::new(true));
+    let paused_clone = paused.clone();
+    let attached = controls.attach(move |event| {
+        let is_paused = paused_clone.load(Ordering::Relaxed);
+        let cmd = match event {
+            MediaControlEvent::Toggle => PlayerCommand::Pause,
+            MediaControlEvent::Play if is_paused => PlayerCommand::Pause,
+            MediaControlEvent::Pause | MediaControlEvent::Stop if !is_paused => {
+                PlayerCommand::Pause
+            }
+            MediaControlEvent::Next => PlayerCommand::PlayNext(TriggerSource::Next),
+            MediaControlEvent::Previous => PlayerCommand::PlayPrev,
+            MediaControlEvent::SetPosition(MediaPosition(pos)) => {
+                PlayerCommand::ChangeProgress(pos.as_secs_f32())
+            }
+            _ => return,
+        };
+        log::info!("system media control: {}", cmd.name());
+        if tx.send(cmd).is_err() {
+            log::warn!("player thread is gone, media control ignored");
+        }
+    });
+    if let Err(e) = attached {
+        log::warn!("failed to attach system media controls: {:?}", e);
+        return;
+    }
+    SESSION.with_borrow_mut(|session| {
+        *session = Some(MediaSession {
+            controls,
+            paused,
+        })
+    });
+    log::info!("system media controls registered");
+}
+
+/// Publish the song that just started, `cover` is an image file used as thumbnail
+pub fn set_song(song: &SongInfo, duration: f32, cover: Option<&Path>) {
+    SESSION.with_borrow_mut(|session| {
+        let Some(session) = session else {
+            return;
+        };
+        let cover_url = cover.map(|x| format!("file://{}", x.display()));
+        let metadata = MediaMetadata {
+            title: Some(&song.song_name),
+            artist: Some(&song.singer),
+            cover_url: cover_url.as_deref(),
+            duration: Some(Duration::from_secs_f32(duration.max(0.))),
+            ..Default::default()
+        };
+        if let Err(e) = session.controls.set_metadata(metadata) {
+            log::warn!("failed to publish media metadata: {:?}", e);
+        }
+    });
+}
+
+/// Publish the pause state, does nothing if it did not change since the last call
+pub fn set_paused(paused: bool) {
+    SESSION.with_borrow_mut(|session| {
+        let Some(session) = session else {
+            return;
+        };
+        if session.paused.swap(paused, Ordering::Relaxed) == paused {
+            return;
+        }
+        let playback = if paused {
+            MediaPlayback::Paused {
+                progress: None,
+            }
+        } else {
+            MediaPlayback::Playing {
+                progress: None,
+            }
+        };
+        if let Err(e) = session.controls.set_playback(playback) {
+            log::warn!("failed to publish playback state: {:?}", e);
+        }
+    });
+}
+
+/// SMTC is bound to a window on Windows, other platforms do not need one
+#[cfg(target_os = "windows")]
+fn window_hwnd(ui: &MainWindow) -> Option<*mut c_void> {
+    use raw_window_handle::{HasWindowHandle, RawWindowHandle};
+    use slint::ComponentHandle;
+    match ui.window().window_handle().window_handle().ok()?.as_raw() {
+        RawWindowHandle::Win32(handle) => Some(handle.hwnd.get() as *mut c_void),
+        _ => None,
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn window_hwnd(_ui: &MainWindow) -> Option<*mut c_void> {
+    None
+}
@@ -0,0 +1,34 @@
+use std::{path::Path, thread};
+
+use notify_rust::{Notification, Timeout};
+
+use crate::slint_types::SongInfo;
+
+/// How long the notification stays on screen where the platform lets us choose
+const SHOW_MILLIS: u32 = 5000;
+
+/// Show a desktop notification about `song`, with the cover thumbnail at `cover_file` if any.
+/// Sending happens on a background thread since some platforms block until it is delivered.
+pub fn now_playing(song: &SongInfo, cover_file: Option<&Path>) {
+    let summary = song.song_name.to_string();
+    let body = [song.singer.as_str(), song.album.as_str()]
+        .into_iter()
+        .filter(|x| !x.trim().is_empty() && *x != "unknown")
+        .collect::<Vec<_>>()
+        .join(" - ");
+    let cover = cover_file.map(|x| x.display().to_string());
+    thread::spawn(move || {
+        let mut notification = Notification::new();
+        notification
+            .appname("Zeedle")
+            .summary(&summary)
+            .body(&body)
+            .timeout(Timeout::Milliseconds(SHOW_MILLIS));
+        if let Some(cover) = cover.as_deref() {
+            notification.image_path(cover);
+        }
+        if let Err(e) = notification.show() {
+            log::warn!("failed to show now playing notification: {}", e);
+        }
+    });
+}
@@ -0,0 +1,205 @@
+use std::{
+    fs,
+    io::Read,
+    path::PathBuf,
+    sync::{
+        OnceLock,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use reqwest::blocking::Client;
+
+use crate::{config, cover_cache, slint_types::SongInfo, stream};
+
+/// MusicBrainz asks clients to identify themselves
+pub const USER_AGENT: &str = concat!("Zeedle/", env!("CARGO_PKG_VERSION"), " (music player)");
+/// MusicBrainz allows one request per second on average
+const MIN_REQUEST_INTERVAL: Duration = Duration::from_millis(1100);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+/// Search results scored lower than this (out of 100) are not the album we are looking for
+const MIN_MATCH_SCORE: u32 = 90;
+
+/// Decoded cover found online: RGBA pixels, width and height
+type FoundCover = (Vec<u8>, u32, u32);
+
+type OnFound = Box<dyn FnOnce(FoundCover) + Send>;
+
+struct Request {
+    artist: String,
+    album: String,
+    on_found: OnFound,
+}
+
+#[derive(serde::Deserialize)]
+struct SearchResult {
+    #[serde(rename = "release-groups", default)]
+    release_groups: Vec<ReleaseGroup>,
+}
+
+#[derive(serde::Deserialize)]
+struct ReleaseGroup {
+    id: String,
+    #[serde(default)]
+    score: u32,
+}
+
+static FETCHER: OnceLock<Sender<Request>> = OnceLock::new();
+
+/// Start the background fetcher, covers are only looked up online after this
+pub fn enable() {
+    FETCHER.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || run(rx));
+        log::info!("online cover lookup enabled");
+        tx
+    });
+}
+
+/// Cover of `song` fetched earlier, read from the cache directory at full size.
+/// Meant to be handed to `CoverCache::insert_found`, which keeps it for later plays
+pub fn cached(song: &SongInfo) -> Option<FoundCover> {
+    FETCHER.get()?;
+    let (artist, album) = album_key(song)?;
+    let bytes = fs::read(cover_path(&artist, &album)).ok()?;
+    let rgba = image::load_from_memory(&bytes).ok()?.into_rgba8();
+    let (width, height) = rgba.dimensions();
+    Some((rgba.into_vec(), width, height))
+}
+
+/// Look up the cover of `song` online in the background, `on_found` runs on the fetcher thread.
+/// Albums already looked up, found or not, are never requested again.
+pub fn fetch(song: &SongInfo, on_found: impl FnOnce(FoundCover) + Send + 'static) {
+    let Some(tx) = FETCHER.get() else {
+        return;
+    };
+    let Some((artist, album)) = album_key(song) else {
+        return;
+    };
+    if cover_path(&artist, &album).exists() || missing_path(&artist, &album).exists() {
+        return;
+    }
+    let _ = tx.send(Request {
+        artist,
+        album,
+        on_found: Box::new(on_found),
+    });
+}
+
+/// Artist and album to search for, `None` if the tags don't tell
+fn album_key(song: &SongInfo) -> Option<(String, String)> {
+    let known = |x: &str| !x.trim().is_empty() && x != "unknown";
+    if stream::is_url(&song.song_path) || !known(&song.singer) || !known(&song.album) {
+        return None;
+    }
+    Some((song.singer.trim().to_string(), song.album.trim().to_string()))
+}
+
+fn cover_dir() -> PathBuf {
+    config::get_cache_dir().join("online_covers")
+}
+
+fn cover_path(artist: &str, album: &str) -> PathBuf {
+    cover_dir().join(format!("{:016x}.img", album_hash(artist, album)))
+}
+
+/// Empty marker of an album not found online, delete it to look it up again
+fn missing_path(artist: &str, album: &str) -> PathBuf {
+    cover_dir().join(format!("{:016x}.none", album_hash(artist, album)))
+}
+
+fn album_hash(artist: &str, album: &str) -> u64 {
+    cover_cache::fnv1a(format!("{}\0{}", artist.to_lowercase(), album.to_lowercase()).as_bytes())
+}
+
+fn run(rx: Receiver<Request>) {
+    let client = match Client::builder()
+        .user_agent(USER_AGENT)
+        .connect_timeout(REQUEST_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            log::warn!("online cover lookup disabled, failed to create http client: {}", e);
+            return;
+        }
+    };
+    let mut last_request: Option<Instant> = None;
+    while let Ok(mut request) = rx.recv() {
+        // 快速切歌时只查找最后一首，之前的歌曲已不再显示
+        while let Ok(newer) = rx.try_recv() {
+            request = newer;
+        }
+        let (artist, album) = (&request.artist, &request.album);
+        if cover_path(artist, album).exists() || missing_path(artist, album).exists() {
+            continue;
+        }
+        if let Some(elapsed) = last_request.map(|x| x.elapsed()) {
+            thread::sleep(MIN_REQUEST_INTERVAL.saturating_sub(elapsed));
+        }
+        last_request = Some(Instant::now());
+        if let Err(e) = fs::create_dir_all(cover_dir()) {
+            log::warn!("failed to create online cover directory: {}", e);
+            continue;
+        }
+        match lookup_cover(&client, artist, album) {
+            Ok(Some(bytes)) => {
+                let Ok(rgba) = image::load_from_memory(&bytes).map(|x| x.into_rgba8()) else {
+                    log::warn!("online cover of <{} - {}> is not an image", artist, album);
+                    continue;
+                };
+                if let Err(e) = fs::write(cover_path(artist, album), &bytes) {
+                    log::warn!("failed to cache online cover: {}", e);
+                }
+                log::info!("found cover of <{} - {}> online", artist, album);
+                let (width, height) = rgba.dimensions();
+                (request.on_found)((rgba.into_vec(), width, height));
+            }
+            Ok(None) => {
+                log::info!("no cover of <{} - {}> online", artist, album);
+                let _ = fs::write(missing_path(artist, album), b"");
+            }
+            // 网络错误不记录为未找到，下次播放时重试
+            Err(e) => log::warn!("failed to look up cover of <{} - {}>: {}", artist, album, e),
+        }
+    }
+}
+
+/// Search MusicBrainz for the album, then download its front cover from the Cover Art Archive
+fn lookup_cover(client: &Client, artist: &str, album: &str) -> Result<Option<Vec<u8>>, String> {
+    let query =
+        format!("releasegroup:\"{}\" AND artist:\"{}\"", escape_term(album), escape_term(artist));
+    let response = client
+        .get("https://musicbrainz.org/ws/2/release-group/")
+        .query(&[("query", query.as_str()), ("fmt", "json"), ("limit", "1")])
+        .send()
+        .and_then(|x| x.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let result: SearchResult = serde_json::from_reader(response).map_err(|e| e.to_string())?;
+    let Some(group) = result.release_groups.into_iter().find(|x| x.score >= MIN_MATCH_SCORE) else {
+        return Ok(None);
+    };
+    let response = client
+        .get(format!("https://coverartarchive.org/release-group/{}/front-500", group.id).as_str())
+        .send()
+        .map_err(|e| e.to_string())?;
+    // 专辑存在但没有封面
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let mut bytes = Vec::new();
+    response
+        .error_for_status()
+        .map_err(|e| e.to_string())?
+        .read_to_end(&mut bytes)
+        .map_err(|e| e.to_string())?;
+    Ok(Some(bytes))
+}
+
+/// Escape quotes and backslashes inside a quoted Lucene search term
+pub fn escape_term(term: &str) -> String {
+    term.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,152 @@
+use std::{sync::Mutex, thread, time::Duration};
+
+use rodio::{
+    OutputStream, OutputStreamBuilder, StreamError, cpal,
+    cpal::traits::{DeviceTrait, HostTrait},
+};
+
+/// How often output devices are polled for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Name of the device the output stream was last opened on, empty for the default device
+static IN_USE: Mutex<String> = Mutex::new(String::new());
+
+/// Output devices seen by one poll
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceSnapshot {
+    /// Name of the system default output device
+    pub default: Option<String>,
+    pub devices: Vec<String>,
+}
+
+impl DeviceSnapshot {
+    fn capture() -> Self {
+        let host = cpal::default_host();
+        let mut devices: Vec<String> = host
+            .output_devices()
+            .map(|devices| devices.filter_map(|d| d.name().ok()).collect())
+            .unwrap_or_default();
+        if let Some(in_use) = busy_in_use().filter(|x| !devices.contains(x)) {
+            devices.push(in_use);
+        }
+        Self {
+            default: host.default_output_device().and_then(|d| d.name().ok()),
+            devices,
+        }
+    }
+
+    /// Whether the device called `name` is available
+    pub fn contains(&self, name: &str) -> bool {
+        self.devices.iter().any(|x| x == name)
+    }
+
+    /// Whether the device in use, `selected` or the default if empty, went away in `after`,
+    /// or the default moved to built-in speakers, as when headphones are unplugged
+    pub fn output_lost(&self, after: &DeviceSnapshot, selected: &str) -> bool {
+        let active = if selected.is_empty() {
+            self.default.as_deref()
+        } else {
+            Some(selected)
+        };
+        let Some(active) = active else {
+            return false;
+        };
+        if self.contains(active) && !after.contains(active) {
+            return true;
+        }
+        // 跟随默认设备时，默认设备切换到扬声器多半是耳机被拔出
+        selected.is_empty()
+            && self.default != after.default
+            && after.default.as_deref().is_some_and(|x| x.to_lowercase().contains("speaker"))
+    }
+}
+
+/// The device in use if it is plugged in but may be missing from enumeration.
+/// ALSA only lists cards it can open, so the busy card we play on drops out of the list;
+/// it still counts as present as long as its card is registered in `/proc/asound/cards`
+#[cfg(target_os = "linux")]
+fn busy_in_use() -> Option<String> {
+    let in_use = IN_USE.lock().unwrap().clone();
+    let cards = std::fs::read_to_string("/proc/asound/cards").ok()?;
+    alsa_card_present(&in_use, &cards).then_some(in_use)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn busy_in_use() -> Option<String> {
+    None
+}
+
+/// Whether the card of ALSA device `name` (e.g. `hw:CARD=Device,DEV=0`) is listed in `cards`,
+/// the contents of `/proc/asound/cards`. Devices not naming a card (`default`, `pipewire`) are never busy
+#[cfg(any(target_os = "linux", test))]
+fn alsa_card_present(name: &str, cards: &str) -> bool {
+    let Some(card) = name.split_once("CARD=").map(|(_, x)| x.split(',').next().unwrap_or(x)) else {
+        return false;
+    };
+    // 每张声卡的首行形如 " 0 [PCH            ]: HDA-Intel - HDA Intel PCH"
+    cards
+        .lines()
+        .filter_map(|x| x.split_once('[')?.1.split_once(']'))
+        .any(|(id, _)| id.trim() == card)
+}
+
+/// Poll output devices on a background thread, calling `on_change(before, after)` whenever they change,
+/// e.g. a USB DAC is unplugged or the system default switches to headphones
+pub fn watch_output_devices(on_change: impl Fn(&DeviceSnapshot, &DeviceSnapshot) + Send + 'static) {
+    thread::spawn(move || {
+        let mut last = DeviceSnapshot::capture();
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let current = DeviceSnapshot::capture();
+            if current != last {
+                log::debug!("output devices changed: {:?}", current);
+                on_change(&last, &current);
+                last = current;
+            }
+        }
+    });
+}
+
+/// Names of the output devices currently available on the default host
+pub fn list_output_devices() -> Vec<String> {
+    match cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+        Err(e) => {
+            log::warn!("failed to list output devices: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Open an output stream on the device called `name`, or on the default device if `name` is empty
+pub fn open_output_stream(name: &str) -> Result<OutputStream, StreamError> {
+    let builder = if name.is_empty() {
+        OutputStreamBuilder::from_default_device()?
+    } else {
+        let device = cpal::default_host()
+            .output_devices()
+            .ok()
+            .and_then(|mut devices| devices.find(|d| d.name().is_ok_and(|x| x == name)))
+            .ok_or(StreamError::NoDevice)?;
+        OutputStreamBuilder::from_device(device)?
+    };
+    let mut stream = builder.with_buffer_size(cpal::BufferSize::Fixed(4096)).open_stream()?;
+    stream.log_on_drop(false);
+    *IN_USE.lock().unwrap() = name.to_string();
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unplugged_card_is_not_present() {
+        let cards = " 0 [PCH            ]: HDA-Intel - HDA Intel PCH\n                      HDA Intel PCH at 0xf7f10000 irq 32\n 1 [Device         ]: USB-Audio - USB Audio Device\n";
+        assert!(alsa_card_present("hw:CARD=Device,DEV=0", cards));
+        assert!(alsa_card_present("front:CARD=PCH,DEV=0", cards));
+        // USB 声卡拔出后不再出现在列表中
+        assert!(!alsa_card_present("hw:CARD=Headset,DEV=0", cards));
+        assert!(!alsa_card_present("default", cards));
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::BTreeSet;
+
+use slint::SharedString;
+
+use crate::{config, slint_types::SongInfo};
+
+/// Per-track flags set by the user, keyed by song path.
+/// Kept apart from the config file since it grows with the library.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Overrides {
+    /// Songs never picked by `PlayNext`, but still playable by clicking them
+    pub skip_autoplay: BTreeSet<String>,
+    /// Songs marked as favorite, keyed by absolute path so they stay favorite across rescans
+    pub favorite: BTreeSet<String>,
+}
+
+impl Overrides {
+    /// Load overrides from file, or return empty overrides if file not exists or invalid
+    pub fn load() -> Self {
+        config::load_toml(&config::get_overrides_path(), "overrides")
+    }
+
+    /// Save overrides to file
+    pub fn save(&self) {
+        config::save_toml(self, &config::get_overrides_path(), "overrides");
+    }
+
+    /// Copy the flags onto freshly scanned songs
+    pub fn apply(&self, songs: &mut [SongInfo]) {
+        for song in songs {
+            song.skip_autoplay = self.skip_autoplay.contains(song.song_path.as_str());
+            song.favorite = self.favorite.contains(song.song_path.as_str());
+        }
+    }
+
+    /// Toggle the autoplay skip flag of `path`, return the new state
+    pub fn toggle_skip_autoplay(&mut self, path: &SharedString) -> bool {
+        toggle(&mut self.skip_autoplay, path)
+    }
+
+    /// Toggle the favorite flag of `path`, return the new state
+    pub fn toggle_favorite(&mut self, path: &SharedString) -> bool {
+        toggle(&mut self.favorite, path)
+    }
+}
+
+fn toggle(set: &mut BTreeSet<String>, path: &SharedString) -> bool {
+    if set.remove(path.as_str()) {
+        false
+    } else {
+        set.insert(path.to_string());
+        true
+    }
+}
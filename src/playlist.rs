@@ -0,0 +1,267 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::slint_types::SongInfo;
+
+/// One track of an imported playlist, as written by the other player
+#[derive(Clone, Debug, Default)]
+pub struct PlaylistEntry {
+    pub path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    /// Duration in seconds, if the playlist records it
+    pub duration: Option<f32>,
+}
+
+/// Whether `path` is an existing `.m3u` or `.m3u8` file
+pub fn is_m3u_file(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    let ext = path.extension().and_then(|x| x.to_str()).unwrap_or("").to_ascii_lowercase();
+    (ext == "m3u" || ext == "m3u8") && path.is_file()
+}
+
+/// Read a `.pls`, `.m3u` or `.m3u8` playlist, relative paths are resolved against the playlist directory
+pub fn read_playlist(path: impl AsRef<Path>) -> io::Result<Vec<PlaylistEntry>> {
+    let path = path.as_ref();
+    let bytes = fs::read(path)?;
+    // .m3u 常见于旧播放器，可能不是 UTF-8 编码
+    let text = String::from_utf8_lossy(&bytes);
+    let text = text.trim_start_matches('\u{feff}');
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    let ext = path.extension().and_then(|x| x.to_str()).unwrap_or("").to_ascii_lowercase();
+    let mut entries = match ext.as_str() {
+        "pls" => parse_pls(text),
+        "m3u" | "m3u8" => parse_m3u(text),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported playlist format: {:?}", path),
+            ));
+        }
+    };
+    for entry in entries.iter_mut() {
+        entry.path = resolve_path(&entry.path, base_dir).display().to_string();
+    }
+    Ok(entries)
+}
+
+/// Parse an (extended) M3U playlist, `#EXTINF:<secs>,<artist> - <title>` lines describe the next path
+fn parse_m3u(text: &str) -> Vec<PlaylistEntry> {
+    let mut entries = Vec::new();
+    let mut pending = PlaylistEntry::default();
+    for line in text.lines().map(str::trim).filter(|x| !x.is_empty()) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            let (secs, name) = info.split_once(',').unwrap_or((info, ""));
+            pending.duration = secs.trim().parse::<f32>().ok().filter(|x| *x > 0.);
+            match name.split_once(" - ") {
+                Some((artist, title)) => {
+                    pending.artist = Some(artist.trim().into());
+                    pending.title = Some(title.trim().into());
+                }
+                None if !name.trim().is_empty() => pending.title = Some(name.trim().into()),
+                None => {}
+            }
+        } else if !line.starts_with('#') {
+            pending.path = line.into();
+            entries.push(std::mem::take(&mut pending));
+        }
+    }
+    entries
+}
+
+/// Parse a PLS playlist made of `FileN=`, `TitleN=` and `LengthN=` keys
+fn parse_pls(text: &str) -> Vec<PlaylistEntry> {
+    let mut entries = HashMap::<u32, PlaylistEntry>::new();
+    for line in text.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim();
+        let (field, index) =
+            key.split_at(key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len()));
+        let Ok(index) = index.parse::<u32>() else {
+            continue;
+        };
+        let entry = entries.entry(index).or_default();
+        match field {
+            "file" => entry.path = value.into(),
+            "title" => entry.title = Some(value.into()),
+            "length" => entry.duration = value.parse::<f32>().ok().filter(|x| *x > 0.),
+            _ => {}
+        }
+    }
+    let mut entries = entries.into_iter().filter(|(_, x)| !x.path.is_empty()).collect::<Vec<_>>();
+    entries.sort_by_key(|(index, _)| *index);
+    entries.into_iter().map(|(_, x)| x).collect()
+}
+
+/// Turn a playlist path into a local path: `file://` URLs are decoded and relative paths are joined to `base_dir`
+fn resolve_path(raw: &str, base_dir: &Path) -> PathBuf {
+    let raw = match raw.strip_prefix("file://") {
+        Some(url) => percent_decode(url.strip_prefix("localhost").unwrap_or(url)),
+        None => raw.to_string(),
+    };
+    // Windows 下的 file:///C:/... 会多出一个前导斜杠
+    let raw = match raw.strip_prefix('/') {
+        Some(rest) if rest.get(1..2) == Some(":") => rest.to_string(),
+        _ => raw,
+    };
+    let path = PathBuf::from(&raw);
+    if path.is_absolute() || raw.get(1..2) == Some(":") {
+        path
+    } else {
+        base_dir.join(path)
+    }
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = text.get(i + 1..i + 3).and_then(|x| u8::from_str_radix(x, 16).ok())
+        {
+            out.push(byte);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Normalize a path for comparison across players and platforms: `/` separators, lowercase
+fn path_key(path: &str) -> String {
+    path.replace('\\', "/").to_lowercase()
+}
+
+/// Normalize a title or artist for fuzzy comparison: lowercase alphanumerics only
+fn tag_key(text: &str) -> String {
+    text.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+/// Whether the tags `entry` records agree with `song`, tags the entry lacks are not checked
+fn tags_agree(entry: &PlaylistEntry, song: &SongInfo) -> bool {
+    let title_ok = entry.title.as_deref().is_none_or(|x| tag_key(x) == tag_key(&song.song_name));
+    let artist_ok = entry.artist.as_deref().is_none_or(|x| tag_key(x) == tag_key(&song.singer));
+    let duration_ok = entry.duration.is_none_or(|x| (x - song.duration_secs).abs() <= 3.);
+    title_ok && artist_ok && duration_ok
+}
+
+/// Maps entries of foreign playlists onto songs of the scanned library.
+/// Entries are matched by exact path first, then by the trailing path components
+/// (for libraries moved to another drive or machine), then by title/artist similarity.
+/// A file name alone is not enough: it must share its parent folder too, or the entry's tags must agree.
+pub struct LibraryMatcher<'a> {
+    by_path: HashMap<String, &'a SongInfo>,
+    by_file_name: HashMap<String, Vec<&'a SongInfo>>,
+    by_title: HashMap<String, Vec<&'a SongInfo>>,
+}
+
+impl<'a> LibraryMatcher<'a> {
+    pub fn new(library: &'a [SongInfo]) -> Self {
+        let mut matcher = Self {
+            by_path: HashMap::new(),
+            by_file_name: HashMap::new(),
+            by_title: HashMap::new(),
+        };
+        for song in library {
+            let key = path_key(&song.song_path);
+            let file_name = key.rsplit('/').next().unwrap_or("").to_string();
+            matcher.by_file_name.entry(file_name).or_default().push(song);
+            matcher.by_path.insert(key, song);
+            matcher.by_title.entry(tag_key(&song.song_name)).or_default().push(song);
+        }
+        matcher
+    }
+
+    /// Find the library song of `entry`, `None` if nothing matches closely enough
+    pub fn find(&self, entry: &PlaylistEntry) -> Option<&'a SongInfo> {
+        let key = path_key(&entry.path);
+        if let Some(song) = self.by_path.get(&key) {
+            return Some(song);
+        }
+        // 路径不同（换了盘符或机器）时，选择末尾路径分量重合最多的同名文件。
+        // 只有文件名相同（如 01.mp3）时不足为据，还需条目记录的标签吻合
+        let has_tags = entry.title.is_some() || entry.duration.is_some();
+        let file_name = key.rsplit('/').next().unwrap_or("");
+        if let Some(candidates) = self.by_file_name.get(file_name) {
+            let common_suffix = |song: &SongInfo| {
+                path_key(&song.song_path)
+                    .rsplit('/')
+                    .zip(key.rsplit('/'))
+                    .take_while(|(a, b)| a == b)
+                    .count()
+            };
+            if let Some(song) = candidates
+                .iter()
+                .filter(|song| common_suffix(song) >= 2 || (has_tags && tags_agree(entry, song)))
+                .max_by_key(|song| common_suffix(song))
+            {
+                return Some(song);
+            }
+        }
+        // 最后按标签匹配：标题相同，有歌手或时长时也需吻合
+        let title = entry.title.as_deref().map(tag_key).filter(|x| !x.is_empty())?;
+        self.by_title.get(&title)?.iter().copied().find(|song| tags_agree(entry, song))
+    }
+}
+
+/// Match every entry against `library`, return matched songs in playlist order and the unmatched entries
+pub fn match_entries(
+    entries: &[PlaylistEntry],
+    library: &[SongInfo],
+) -> (Vec<SongInfo>, Vec<PlaylistEntry>) {
+    let matcher = LibraryMatcher::new(library);
+    let mut matched = Vec::new();
+    let mut unmatched = Vec::new();
+    for entry in entries {
+        match matcher.find(entry) {
+            Some(song) => matched.push(song.clone()),
+            None => unmatched.push(entry.clone()),
+        }
+    }
+    (matched, unmatched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(path: &str, name: &str) -> SongInfo {
+        SongInfo {
+            song_path: path.into(),
+            song_name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    fn entry(path: &str, title: Option<&str>) -> PlaylistEntry {
+        PlaylistEntry {
+            path: path.into(),
+            title: title.map(Into::into),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn bare_file_name_needs_folder_or_tags() {
+        let library =
+            [song("/music/Album A/01.mp3", "Intro"), song("/music/Album B/02.mp3", "Song")];
+        let matcher = LibraryMatcher::new(&library);
+        // 同一专辑文件夹搬到了别的盘
+        let found = matcher.find(&entry("D:/backup/Album A/01.mp3", None));
+        assert_eq!(found.map(|x| x.song_path.as_str()), Some("/music/Album A/01.mp3"));
+        // 只有文件名相同，不能认定是同一首
+        assert!(matcher.find(&entry("D:/other/01.mp3", None)).is_none());
+        assert!(matcher.find(&entry("D:/other/01.mp3", Some("Outro"))).is_none());
+        let found = matcher.find(&entry("D:/other/01.mp3", Some("Intro")));
+        assert_eq!(found.map(|x| x.song_path.as_str()), Some("/music/Album A/01.mp3"));
+    }
+}
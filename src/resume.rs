@@ -0,0 +1,64 @@
+use std::{collections::BTreeMap, path::Path};
+
+use crate::config;
+
+/// Where playback stopped within one album
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct ResumePoint {
+    pub song_path: String,
+    /// Position in seconds
+    pub progress: f32,
+}
+
+/// Last played track and position of every album, so returning to an album continues from there.
+/// Albums are keyed by folder, since tracks of one release usually share a directory.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct AlbumResume {
+    pub albums: BTreeMap<String, ResumePoint>,
+}
+
+/// Key of the album `song_path` belongs to: its parent folder
+pub fn album_key(song_path: &str) -> String {
+    Path::new(song_path).parent().map(|x| x.display().to_string()).unwrap_or_default()
+}
+
+impl AlbumResume {
+    /// Load resume points from file, or return empty ones if file not exists or invalid
+    pub fn load() -> Self {
+        config::load_toml(&config::get_resume_path(), "album resume")
+    }
+
+    /// Save resume points to file
+    pub fn save(&self) {
+        config::save_toml(self, &config::get_resume_path(), "album resume");
+    }
+
+    /// Remember `progress` of `song_path` as the resume point of its album.
+    /// A track played to its end clears the point instead, so the finished album starts over next time.
+    pub fn record(&mut self, song_path: &str, progress: f32, duration: f32) {
+        if song_path.is_empty() {
+            return;
+        }
+        let key = album_key(song_path);
+        if duration > 0. && progress >= duration - 1. {
+            // 同一专辑的下一首会在开始播放时重新记录
+            if self.albums.get(&key).is_some_and(|x| x.song_path == song_path) {
+                self.albums.remove(&key);
+            }
+            return;
+        }
+        self.albums.insert(
+            key,
+            ResumePoint {
+                song_path: song_path.into(),
+                progress,
+            },
+        );
+    }
+
+    /// Resume point of the album `song_path` belongs to
+    pub fn get(&self, song_path: &str) -> Option<&ResumePoint> {
+        self.albums.get(&album_key(song_path))
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashSet;
+
+use rand::seq::SliceRandom;
+use slint::SharedString;
+
+/// Play order of random mode, a shuffled permutation of song paths.
+/// Every song plays once per cycle, then the order is reshuffled.
+#[derive(Default)]
+pub struct ShuffleBag {
+    order: Vec<SharedString>,
+    /// Index in `order` of the next song to play
+    pos: usize,
+}
+
+impl ShuffleBag {
+    /// Path of the next song to play among `candidates`, `current` is the song playing now.
+    /// A new order is drawn when the cycle ends or the candidates changed (e.g. new search or folder).
+    pub fn next(&mut self, candidates: &[SharedString], current: &str) -> Option<SharedString> {
+        let unchanged = self.order.len() == candidates.len() && {
+            let order = self.order.iter().collect::<HashSet<_>>();
+            candidates.iter().all(|x| order.contains(x))
+        };
+        if !unchanged {
+            self.reshuffle(candidates.to_vec(), current);
+            // 候选歌曲变化时，正在播放的歌曲算作本轮已播放
+            if let Some(idx) = self.order.iter().position(|x| x == current) {
+                self.order.swap(0, idx);
+                self.pos = 1;
+            }
+        }
+        // 点击播放的歌曲可能正好排在洗牌顺序的下一首，视为本轮已播放，避免连续重复
+        if self.order.len() > 1 && self.order.get(self.pos).is_some_and(|x| x == current) {
+            self.pos += 1;
+        }
+        if self.pos >= self.order.len() {
+            let order = std::mem::take(&mut self.order);
+            self.reshuffle(order, current);
+        }
+        let path = self.order.get(self.pos)?.clone();
+        self.pos += 1;
+        Some(path)
+    }
+
+    fn reshuffle(&mut self, mut order: Vec<SharedString>, current: &str) {
+        order.shuffle(&mut rand::rng());
+        // 新一轮的第一首不能是刚播放的歌曲，避免连续重复
+        if order.len() > 1 && order[0] == current {
+            let last = order.len() - 1;
+            order.swap(0, last);
+        }
+        log::info!("reshuffled {} songs", order.len());
+        self.order = order;
+        self.pos = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_songs_always_alternate() {
+        let (a, b) = (SharedString::from("a.mp3"), SharedString::from("b.mp3"));
+        let songs = [a.clone(), b.clone()];
+        let mut bag = ShuffleBag::default();
+        for _ in 0..300 {
+            assert_eq!(bag.next(&songs, &a), Some(b.clone()));
+            // 每次都从新的洗牌状态开始也一样
+            assert_eq!(ShuffleBag::default().next(&songs, &a), Some(b.clone()));
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+
+/// Number of bars of the spectrum visualizer
+pub const SPECTRUM_BARS: usize = 32;
+
+/// Frequency range (Hz) shown by the visualizer, split into log-spaced bars
+const MIN_FREQ: f32 = 40.;
+const MAX_FREQ: f32 = 16000.;
+
+/// Level (dB relative to full scale) shown as an empty bar, 0 dB is a full one
+const FLOOR_DB: f32 = -70.;
+
+/// FFT of a fixed window size, reused for every frame of the visualizer
+pub struct SpectrumAnalyzer {
+    fft: Arc<dyn Fft<f32>>,
+    /// Hann window, reduces the leakage of loud bands into their neighbours
+    window: Vec<f32>,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(size: usize) -> Self {
+        let window =
+            (0..size).map(|i| 0.5 - 0.5 * (2. * PI * i as f32 / (size - 1) as f32).cos()).collect();
+        Self {
+            fft: FftPlanner::new().plan_fft_forward(size),
+            window,
+        }
+    }
+
+    /// Levels of `samples` (mono, as many as the window size) in `SPECTRUM_BARS` log-spaced bands, scaled to 0~1
+    pub fn bars(&self, samples: &[f32], sample_rate: u32) -> Vec<f32> {
+        let size = self.window.len();
+        let mut buffer = samples
+            .iter()
+            .zip(&self.window)
+            .map(|(x, w)| Complex::new(x * w, 0.))
+            .collect::<Vec<_>>();
+        buffer.resize(size, Complex::default());
+        self.fft.process(&mut buffer);
+        // Hann 窗的平均增益为 0.5，幅度按单边谱换算，满幅正弦波约为 0 dB
+        let scale = 4. / size as f32;
+        let bin_of =
+            |freq: f32| ((freq * size as f32 / sample_rate.max(1) as f32) as usize).min(size / 2);
+        let ratio = MAX_FREQ / MIN_FREQ;
+        (0..SPECTRUM_BARS)
+            .map(|bar| {
+                let low = MIN_FREQ * ratio.powf(bar as f32 / SPECTRUM_BARS as f32);
+                let high = MIN_FREQ * ratio.powf((bar + 1) as f32 / SPECTRUM_BARS as f32);
+                let (low, high) = (bin_of(low), bin_of(high));
+                let peak = buffer[low..high.max(low + 1).min(size / 2 + 1)]
+                    .iter()
+                    .map(|x| x.norm() * scale)
+                    .fold(0., f32::max);
+                let db = 20. * peak.max(1e-9).log10();
+                ((db - FLOOR_DB) / -FLOOR_DB).clamp(0., 1.)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Cursor, Read, Seek, SeekFrom},
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+    },
+    thread,
+    time::Duration,
+};
+
+use reqwest::{
+    StatusCode,
+    blocking::{Client, Response},
+    header,
+};
+use rodio::Decoder;
+
+use crate::slint_types::SongInfo;
+
+/// Give up connecting to a server after this long
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// A stream delivering nothing for this long is given up, ending the song.
+/// Reads run in the audio callback, so a stalled server must not block it for longer
+const READ_TIMEOUT: Duration = Duration::from_secs(3);
+/// Bytes per chunk read ahead by the download thread
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks downloaded ahead of the decoder (2 MiB), so network hiccups don't reach the audio callback
+const READ_AHEAD_CHUNKS: usize = 32;
+
+/// Whether `path` is an http(s) URL rather than a local file
+pub fn is_url(path: &str) -> bool {
+    let path = path.trim_start().to_ascii_lowercase();
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// SongInfo of a remote song, named after the last segment of the URL since its tags aren't read
+pub fn url_song_info(url: &str) -> SongInfo {
+    let url = url.trim();
+    let without_query = url.split(['?', '#']).next().unwrap_or(url);
+    let (host, path) = without_query
+        .split_once("://")
+        .map_or(("", without_query), |(_, rest)| rest.split_once('/').unwrap_or((rest, "")));
+    let name = path.rsplit('/').find(|x| !x.is_empty()).unwrap_or(host);
+    SongInfo {
+        song_path: url.into(),
+        song_name: name.into(),
+        singer: host.into(),
+        duration: "00:00".into(),
+        genre: "unknown".into(),
+        album: "unknown".into(),
+        ..Default::default()
+    }
+}
+
+/// Bytes of a song, read from a local file or streamed from a server
+pub enum SongReader {
+    File(BufReader<File>),
+    Http(HttpReader),
+}
+
+impl Read for SongReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SongReader::File(x) => x.read(buf),
+            SongReader::Http(x) => x.read(buf),
+        }
+    }
+}
+
+impl Seek for SongReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            SongReader::File(x) => x.seek(pos),
+            SongReader::Http(x) => x.seek(pos),
+        }
+    }
+}
+
+/// Open a decoder for `path`, a local file or an http(s) URL streamed while playing
+pub fn open_decoder(path: &str) -> Result<Decoder<SongReader>, String> {
+    let builder = if is_url(path) {
+        let reader = HttpReader::open(path.trim()).map_err(|e| e.to_string())?;
+        let mut builder = Decoder::builder().with_seekable(reader.seekable);
+        if let Some(len) = reader.len {
+            builder = builder.with_byte_len(len);
+        }
+        if let Some(mime_type) = reader.mime_type.clone() {
+            builder = builder.with_mime_type(&mime_type);
+        }
+        builder.with_data(SongReader::Http(reader))
+    } else {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let len = file.metadata().map_err(|e| e.to_string())?.len();
+        Decoder::builder()
+            .with_byte_len(len)
+            .with_seekable(true)
+            .with_data(SongReader::File(BufReader::new(file)))
+    };
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Reader streaming a remote file over HTTP, seeks are served by range requests.
+/// The body is downloaded ahead on a background thread
+pub struct HttpReader {
+    client: Client,
+    url: String,
+    /// Chunks read ahead by the download thread, which stops once this is dropped.
+    /// Decoders must be `Sync`, which a bare `Receiver` isn't
+    chunks: Mutex<Receiver<io::Result<Vec<u8>>>>,
+    /// Rest of the chunk being read
+    pending: Cursor<Vec<u8>>,
+    /// Offset of the next byte `read` yields
+    pos: u64,
+    /// Size of the file, if the server told
+    len: Option<u64>,
+    /// Whether the server accepts range requests
+    seekable: bool,
+    mime_type: Option<String>,
+}
+
+impl HttpReader {
+    fn open(url: &str) -> io::Result<Self> {
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(None)
+            .build()
+            .map_err(io::Error::other)?;
+        let response =
+            client.get(url).send().and_then(|x| x.error_for_status()).map_err(io::Error::other)?;
+        let header_value = |name| response.headers().get(name).and_then(|x| x.to_str().ok());
+        let seekable = header_value(header::ACCEPT_RANGES).is_some_and(|x| x.contains("bytes"));
+        let mime_type = header_value(header::CONTENT_TYPE)
+            .map(|x| x.split(';').next().unwrap_or(x).trim().to_string());
+        log::info!(
+            "streaming <{}>, {:?} bytes, seekable: {}",
+            url,
+            response.content_length(),
+            seekable
+        );
+        Ok(Self {
+            len: response.content_length(),
+            client,
+            url: url.to_string(),
+            chunks: Mutex::new(spawn_download(response)),
+            pending: Cursor::new(Vec::new()),
+            pos: 0,
+            seekable,
+            mime_type,
+        })
+    }
+
+    /// Restart the download at byte `pos`. The range request is sent on the download thread
+    /// and waited for at most `READ_TIMEOUT`, since seeks run in the audio callback
+    fn request_from(&mut self, pos: u64) -> io::Result<()> {
+        let (tx, rx) = mpsc::sync_channel(READ_AHEAD_CHUNKS);
+        let request = self.client.get(&self.url).header(header::RANGE, format!("bytes={}-", pos));
+        thread::spawn(move || match request.send().and_then(|x| x.error_for_status()) {
+            Ok(response) if response.status() == StatusCode::PARTIAL_CONTENT => {
+                download(response, &tx)
+            }
+            Ok(_) => {
+                let _ = tx.send(Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "server ignored the range request",
+                )));
+            }
+            Err(e) => {
+                let _ = tx.send(Err(io::Error::other(e)));
+            }
+        });
+        let first = match rx.recv_timeout(READ_TIMEOUT) {
+            Ok(chunk) => chunk?,
+            // 从文件末尾开始时没有数据
+            Err(RecvTimeoutError::Disconnected) => Vec::new(),
+            Err(RecvTimeoutError::Timeout) => {
+                log::warn!("<{}> didn't answer a seek within {:?}", self.url, READ_TIMEOUT);
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "seek timed out"));
+            }
+        };
+        // 替换接收端后旧的下载线程在下次发送时退出
+        self.chunks = Mutex::new(rx);
+        self.pending = Cursor::new(first);
+        self.pos = pos;
+        Ok(())
+    }
+}
+
+/// Read `response` into chunks on a background thread, at most `READ_AHEAD_CHUNKS` ahead of the receiver.
+/// The channel closes at the end of the body, after an error is sent, or once the receiver is dropped
+fn spawn_download(response: Response) -> Receiver<io::Result<Vec<u8>>> {
+    let (tx, rx) = mpsc::sync_channel(READ_AHEAD_CHUNKS);
+    thread::spawn(move || download(response, &tx));
+    rx
+}
+
+/// Send the body of `response` to `tx` chunk by chunk, until it ends, fails or the receiver is gone
+fn download(mut response: Response, tx: &SyncSender<io::Result<Vec<u8>>>) {
+    loop {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        match response.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => {
+                chunk.truncate(n);
+                if tx.send(Ok(chunk)).is_err() {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                let _ = tx.send(Err(e));
+                break;
+            }
+        }
+    }
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.position() as usize >= self.pending.get_ref().len() {
+            match self.chunks.get_mut().unwrap().recv_timeout(READ_TIMEOUT) {
+                Ok(chunk) => self.pending = Cursor::new(chunk?),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+                Err(RecvTimeoutError::Timeout) => {
+                    log::warn!("<{}> stalled for {:?}, give up", self.url, READ_TIMEOUT);
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "stream stalled"));
+                }
+            }
+        }
+        let n = self.pending.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for HttpReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::Current(x) => self.pos.checked_add_signed(x),
+            SeekFrom::End(x) => {
+                let len = self.len.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::Unsupported, "stream length unknown")
+                })?;
+                len.checked_add_signed(x)
+            }
+        }
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek before start of stream")
+        })?;
+        // 短距离向前跳转直接读取丢弃，比重新请求更快
+        if target >= self.pos && target - self.pos <= 64 * 1024 {
+            let skip = target - self.pos;
+            io::copy(&mut self.by_ref().take(skip), &mut io::sink())?;
+        } else if target != self.pos {
+            self.request_from(target)?;
+        }
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    cell::RefCell,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+    },
+};
+
+use slint::ComponentHandle;
+use tray_icon::{
+    Icon, MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent,
+    menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem},
+};
+
+use crate::{
+    PlayerCommand,
+    slint_types::{MainWindow, SongInfo, TriggerSource},
+};
+
+const PLAY_PAUSE_ID: &str = "play_pause";
+const PREV_ID: &str = "prev";
+const NEXT_ID: &str = "next";
+const SHOW_HIDE_ID: &str = "show_hide";
+const QUIT_ID: &str = "quit";
+
+/// Set once the icon is in the tray, until then closing the window can't hide it there
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// What the tray shows about the player
+struct TrayState {
+    tooltip: String,
+    paused: bool,
+}
+
+/// Tray icon with the menu items whose text follows the player
+struct Tray {
+    icon: TrayIcon,
+    play_pause: MenuItem,
+}
+
+impl Tray {
+    fn build() -> Option<Self> {
+        let play_pause = MenuItem::with_id(PLAY_PAUSE_ID, "Play", true, None);
+        let menu = Menu::new();
+        let appended = menu.append_items(&[
+            &play_pause,
+            &MenuItem::with_id(PREV_ID, "Previous", true, None),
+            &MenuItem::with_id(NEXT_ID, "Next", true, None),
+            &PredefinedMenuItem::separator(),
+            &MenuItem::with_id(SHOW_HIDE_ID, "Show/Hide window", true, None),
+            &MenuItem::with_id(QUIT_ID, "Quit", true, None),
+        ]);
+        if let Err(e) = appended {
+            log::warn!("failed to build tray menu: {}", e);
+            return None;
+        }
+        let icon = TrayIconBuilder::new()
+            .with_menu(Box::new(menu))
+            .with_icon(app_icon()?)
+            .with_tooltip("Zeedle")
+            .build()
+            .inspect_err(|e| log::warn!("system tray unavailable: {}", e))
+            .ok()?;
+        ACTIVE.store(true, Ordering::SeqCst);
+        log::info!("tray icon created");
+        Some(Self {
+            icon,
+            play_pause,
+        })
+    }
+
+    fn show(&self, state: &TrayState) {
+        if let Err(e) = self.icon.set_tooltip(Some(&state.tooltip)) {
+            log::warn!("failed to set tray tooltip: {}", e);
+        }
+        self.play_pause.set_text(if state.paused {
+            "Play"
+        } else {
+            "Pause"
+        });
+    }
+}
+
+/// Tray of this platform, owned by the UI thread
+#[cfg(not(target_os = "linux"))]
+type Handle = Tray;
+
+/// On Linux the tray needs a GTK event loop, which runs in its own thread
+#[cfg(target_os = "linux")]
+type Handle = Sender<TrayState>;
+
+thread_local! {
+    // 托盘状态只在 UI 线程中更新
+    static TRAY: RefCell<Option<(Handle, bool)>> = const { RefCell::new(None) };
+}
+
+/// Put the app icon in the system tray, routing its menu into the player channel.
+/// Must be called on the UI thread once the event loop runs, failures only log a warning.
+pub fn init(ui: &MainWindow, tx: Sender<PlayerCommand>) {
+    let ui_weak = ui.as_weak();
+    MenuEvent::set_event_handler(Some(move |event: MenuEvent| {
+        let cmd = match event.id.0.as_str() {
+            PLAY_PAUSE_ID => PlayerCommand::Pause,
+            PREV_ID => PlayerCommand::PlayPrev,
+            NEXT_ID => PlayerCommand::PlayNext(TriggerSource::Next),
+            SHOW_HIDE_ID => {
+                toggle_window(ui_weak.clone());
+                return;
+            }
+            QUIT_ID => {
+                log::info!("quit from tray");
+                let _ = slint::quit_event_loop();
+                return;
+            }
+            _ => return,
+        };
+        log::info!("tray menu: {}", cmd.name());
+        if tx.send(cmd).is_err() {
+            log::warn!("player thread is gone, tray menu ignored");
+        }
+    }));
+    let ui_weak = ui.as_weak();
+    TrayIconEvent::set_event_handler(Some(move |event: TrayIconEvent| {
+        // 单击托盘图标显示或隐藏窗口，Linux 上不会收到单击事件
+        if let TrayIconEvent::Click {
+            button: MouseButton::Left,
+            button_state: MouseButtonState::Up,
+            ..
+        } = event
+        {
+            toggle_window(ui_weak.clone());
+        }
+    }));
+    let Some(handle) = spawn_tray() else {
+        return;
+    };
+    TRAY.with_borrow_mut(|tray| *tray = Some((handle, true)));
+}
+
+/// Show the song that is now current in the tooltip
+pub fn set_song(song: &SongInfo) {
+    TRAY.with_borrow(|tray| {
+        if let Some((handle, paused)) = tray {
+            update(handle, song, *paused);
+        }
+    });
+}
+
+/// Switch the play/pause item, does nothing if the pause state did not change
+pub fn set_paused(song: &SongInfo, paused: bool) {
+    TRAY.with_borrow_mut(|tray| {
+        if let Some((handle, last)) = tray
+            && *last != paused
+        {
+            *last = paused;
+            update(handle, song, paused);
+        }
+    });
+}
+
+/// Whether the icon is in the tray, so a hidden window can still be brought back
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+fn tray_state(song: &SongInfo, paused: bool) -> TrayState {
+    let tooltip = if song.song_path.is_empty() {
+        "Zeedle".to_string()
+    } else {
+        format!("{} - {}", song.song_name, song.singer)
+    };
+    TrayState {
+        tooltip,
+        paused,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn spawn_tray() -> Option<Handle> {
+    Tray::build()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn update(tray: &Handle, song: &SongInfo, paused: bool) {
+    tray.show(&tray_state(song, paused));
+}
+
+#[cfg(target_os = "linux")]
+fn spawn_tray() -> Option<Handle> {
+    let (tx, rx) = std::sync::mpsc::channel::<TrayState>();
+    std::thread::spawn(move || {
+        if let Err(e) = gtk::init() {
+            log::warn!("system tray unavailable, failed to init gtk: {}", e);
+            return;
+        }
+        let Some(tray) = Tray::build() else {
+            return;
+        };
+        // 定时取出 UI 线程发来的最新状态
+        gtk::glib::timeout_add_local(std::time::Duration::from_millis(200), move || {
+            if let Some(state) = rx.try_iter().last() {
+                tray.show(&state);
+            }
+            gtk::glib::ControlFlow::Continue
+        });
+        gtk::main();
+    });
+    Some(tx)
+}
+
+#[cfg(target_os = "linux")]
+fn update(tx: &Handle, song: &SongInfo, paused: bool) {
+    let _ = tx.send(tray_state(song, paused));
+}
+
+/// Hide the window if shown, otherwise bring it back to the front
+fn toggle_window(ui_weak: slint::Weak<MainWindow>) {
+    let _ = slint::invoke_from_event_loop(move || {
+        if let Some(ui) = ui_weak.upgrade() {
+            if ui.window().is_visible() && !ui.window().is_minimized() {
+                let _ = ui.hide();
+            } else {
+                ui.window().set_minimized(false);
+                ui.show().expect("failed to show UI");
+            }
+        }
+    });
+}
+
+/// The app icon decoded from the bundled .ico
+fn app_icon() -> Option<Icon> {
+    let rgba = image::load_from_memory(include_bytes!("../ui/cover.ico"))
+        .inspect_err(|e| log::warn!("failed to decode tray icon: {}", e))
+        .ok()?
+        .into_rgba8();
+    let (width, height) = rgba.dimensions();
+    Icon::from_rgba(rgba.into_vec(), width, height)
+        .inspect_err(|e| log::warn!("invalid tray icon: {}", e))
+        .ok()
+}
@@ -1,154 +1,1082 @@
-use std::path::Path;
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{self, Cursor, Write},
+    path::{Path, PathBuf},
+    sync::{
+        OnceLock, RwLock,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    time::{Instant, SystemTime},
+};
 
-use globset::GlobBuilder;
+use fuzzy_matcher::{FuzzyMatcher, skim::SkimMatcherV2};
+use globset::{GlobBuilder, GlobMatcher};
 use lofty::{
-    file::{AudioFile, TaggedFileExt},
+    config::WriteOptions,
+    file::{AudioFile, FileType, TaggedFileExt},
     picture::PictureType,
-    tag::{Accessor, ItemKey},
+    tag::{Accessor, ItemKey, Tag, TagExt},
+};
+use rand::{
+    distr::{Distribution, weighted::WeightedIndex},
+    seq::IndexedRandom,
 };
 use rayon::{
-    iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator},
+    ThreadPool, ThreadPoolBuilder,
+    iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator},
     slice::ParallelSliceMut,
 };
+use rodio::Source;
 use slint::{SharedString, ToSharedString};
 use walkdir::WalkDir;
 
-use crate::slint_types::{LyricItem, SongInfo, SortKey};
+use crate::{
+    config::{RadioWeights, ReplayGainMode},
+    library_cache::{self, LibraryCache},
+    logger, playlist,
+    slint_types::{FolderItem, LyricItem, LyricWord, SongGroup, SongGrouping, SongInfo, SortKey},
+    stream,
+};
+
+/// Largest edge of an image handed to the renderer, bigger covers are downscaled first.
+/// Slint doesn't report the GPU texture limit, so this is a conservative value every driver supports.
+static MAX_TEXTURE_SIZE: AtomicU32 = AtomicU32::new(4096);
+
+/// R128 tags are relative to -23 LUFS while ReplayGain targets -18 LUFS,
+/// so an R128 gain needs 5 dB more to land on the ReplayGain reference level
+const R128_TO_REPLAYGAIN_DB: f32 = 5.0;
+
+/// Read loudness normalization gains (track, album) in dB relative to the ReplayGain reference.
+/// Opus files carry `R128_TRACK_GAIN`/`R128_ALBUM_GAIN` as Q7.8 fixed point dB, which take precedence;
+/// otherwise `REPLAYGAIN_*_GAIN` tags like "-6.54 dB" are used. Missing gains are 0 dB.
+/// Note that R128 gains are relative to the Opus header output gain, which the decoder already applies.
+fn read_gain_db(tag: &Tag, file_type: FileType) -> (f32, f32) {
+    let r128 = |key: &str| {
+        tag.get_string(&ItemKey::Unknown(key.into()))
+            .and_then(|x| x.trim().parse::<i16>().ok())
+            .map(|q| q as f32 / 256. + R128_TO_REPLAYGAIN_DB)
+    };
+    let replay_gain = |key: &ItemKey| {
+        tag.get_string(key).and_then(|x| x.trim().trim_end_matches("dB").trim().parse::<f32>().ok())
+    };
+    let (r128_track, r128_album) = if file_type == FileType::Opus {
+        (r128("R128_TRACK_GAIN"), r128("R128_ALBUM_GAIN"))
+    } else {
+        (None, None)
+    };
+    let track = r128_track.or_else(|| replay_gain(&ItemKey::ReplayGainTrackGain));
+    let album = r128_album.or_else(|| replay_gain(&ItemKey::ReplayGainAlbumGain));
+    (track.unwrap_or(0.), album.or(track).unwrap_or(0.))
+}
 
-/// Read meta info from audio file `fp`, return a SongInfo
+/// Linear amplification factor of `song` under the given normalization mode
+pub fn gain_factor(song: &SongInfo, mode: ReplayGainMode) -> f32 {
+    let db = match mode {
+        ReplayGainMode::Off => 0.,
+        ReplayGainMode::Track => song.track_gain,
+        ReplayGainMode::Album => song.album_gain,
+    };
+    10_f32.powf(db / 20.)
+}
+
+/// Read meta info from audio file `fp`, return a SongInfo.
+/// Files without any tag are still listed, named after their file stem with an unknown singer.
 pub fn read_meta_info(path: impl AsRef<Path>) -> Option<SongInfo> {
     let path = path.as_ref();
-    if let Ok(tagged) = lofty::read_from_path(path) {
-        let dura = tagged.properties().duration().as_secs_f32();
-        if let Some(tag) = tagged.primary_tag() {
-            let song_name = tag.title();
-            let song_name = song_name
-                .as_deref()
-                .unwrap_or(path.file_stem().and_then(|x| x.to_str()).unwrap_or("unknown"));
-            let singer_name = tag.artist();
-            let singer_name = singer_name.as_deref().unwrap_or("unknown");
-
-            let item = SongInfo {
-                id: 0,
-                song_path: path.display().to_shared_string(),
-                song_name: song_name.into(),
-                singer: singer_name.into(),
-                duration: format!("{:02}:{:02}", (dura as u32) / 60, (dura as u32) % 60)
-                    .to_shared_string(),
-            };
-            return Some(item);
+    let tagged = lofty::read_from_path(path).ok()?;
+    let dura = tagged.properties().duration().as_secs_f32();
+    // 没有主标签时（如 WAV 只带 RIFF INFO）退而使用其他标签，完全没有标签时只用文件名
+    let tag = tagged.primary_tag().or_else(|| tagged.first_tag());
+    // 标签缺少标题/歌手时，回退到内嵌 LRC 歌词的 [ti:]/[ar:] 元数据
+    let lrc_meta = match tag {
+        Some(tag) if tag.title().is_none() || tag.artist().is_none() => {
+            tag.get_string(&ItemKey::Lyrics).map(|x| parse_lrc(x).1).unwrap_or_default()
         }
+        _ => LrcMeta::default(),
+    };
+    let song_name = tag.and_then(|x| x.title()).map(|x| x.into_owned()).or(lrc_meta.title);
+    let song_name = song_name
+        .as_deref()
+        .unwrap_or(path.file_stem().and_then(|x| x.to_str()).unwrap_or("unknown"));
+    let singer_name = tag.and_then(|x| x.artist()).map(|x| x.into_owned()).or(lrc_meta.artist);
+    let singer_name = singer_name.as_deref().unwrap_or("unknown");
+    let genre = tag.and_then(|x| x.genre());
+    let genre = genre.as_deref().unwrap_or("unknown");
+    let album = tag.and_then(|x| x.album());
+    let album = album.as_deref().unwrap_or("unknown");
+    let track_number = tag.and_then(|x| x.track()).unwrap_or(0);
+    let (track_gain, album_gain) =
+        tag.map(|x| read_gain_db(x, tagged.file_type())).unwrap_or((0., 0.));
+    // 年份标签可能是 "2001" 或完整日期 "2001-05-03"，只取年份
+    let year = tag
+        .and_then(|x| x.get_string(&ItemKey::Year).or(x.get_string(&ItemKey::RecordingDate)))
+        .and_then(|x| x.trim().get(..4)?.parse::<i32>().ok())
+        .unwrap_or(0);
+    // 加入曲库的时间：拷贝进来的文件可能保留了旧的修改时间，所以优先用创建时间
+    let date_added = std::fs::metadata(path)
+        .and_then(|meta| meta.created().or_else(|_| meta.modified()))
+        .map(unix_secs)
+        .unwrap_or(0);
+
+    Some(SongInfo {
+        id: 0,
+        song_path: path.display().to_shared_string(),
+        song_name: song_name.into(),
+        singer: singer_name.into(),
+        duration: format_duration(dura),
+        duration_secs: dura,
+        genre: genre.into(),
+        album: album.into(),
+        track_number: track_number.min(i32::MAX as u32) as i32,
+        track_gain,
+        album_gain,
+        skip_autoplay: false,
+        favorite: false,
+        year,
+        date_added: date_added.min(i32::MAX as u64) as i32,
+    })
+}
+
+/// Write title, singer and album of `song` back to the tags of audio file `path`, empty fields are removed.
+/// A file without tags gets a new tag of its primary type.
+/// The tag is written to a copy which then replaces the file, so a failed write never leaves a corrupted file.
+pub fn write_meta_info(path: impl AsRef<Path>, song: &SongInfo) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut tagged = lofty::read_from_path(path).map_err(io::Error::other)?;
+    if tagged.primary_tag().is_none() {
+        let tag_type = tagged.primary_tag_type();
+        tagged.insert_tag(Tag::new(tag_type));
     }
-    None
+    let tag = tagged.primary_tag_mut().expect("primary tag was just inserted");
+    let value = |x: &SharedString| Some(x.trim().to_string()).filter(|x| !x.is_empty());
+    match value(&song.song_name) {
+        Some(x) => tag.set_title(x),
+        None => tag.remove_title(),
+    }
+    match value(&song.singer) {
+        Some(x) => tag.set_artist(x),
+        None => tag.remove_artist(),
+    }
+    match value(&song.album) {
+        Some(x) => tag.set_album(x),
+        None => tag.remove_album(),
+    }
+    // 临时文件放在同一目录下，保证 rename 不跨文件系统，是原子操作
+    let file_name = path.file_name().map(|x| x.to_string_lossy()).unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(".{}.zeedle-tmp", file_name));
+    let result = std::fs::copy(path, &tmp_path)
+        .and_then(|_| {
+            tag.save_to_path(&tmp_path, WriteOptions::default()).map_err(io::Error::other)
+        })
+        .and_then(|_| std::fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
 }
 
-/// Scan songs in Path `p` and return a list of SongInfo
-pub fn read_song_list(
-    audio_dir: impl AsRef<Path>,
-    sort_key: SortKey,
-    ascending: bool,
-) -> Vec<SongInfo> {
-    let audio_dir = audio_dir.as_ref();
-    if !audio_dir.exists() {
-        return Vec::new();
+/// Number of bars in the waveform preview under the progress bar
+pub const WAVEFORM_BUCKETS: usize = 300;
+
+/// Decode audio file `path` into `buckets` peak amplitudes for a waveform preview,
+/// each the loudest sample of its slice of the track, normalized so the loudest bucket is 1.
+/// `duration` (secs) is used when the decoder can't tell the length.
+/// The whole file is decoded, so call it on a background thread.
+pub fn compute_peaks(path: impl AsRef<Path>, duration: f32, buckets: usize) -> Option<Vec<f32>> {
+    let file = std::fs::File::open(path).ok()?;
+    let source = rodio::Decoder::try_from(file).ok()?;
+    let secs = source.total_duration().map_or(duration as f64, |x| x.as_secs_f64());
+    let samples = secs * source.sample_rate() as f64 * source.channels() as f64;
+    let per_bucket = ((samples / buckets as f64).ceil() as usize).max(1);
+    let mut peaks = vec![0f32; buckets];
+    for (idx, sample) in source.enumerate() {
+        let bucket = &mut peaks[(idx / per_bucket).min(buckets - 1)];
+        *bucket = bucket.max(sample.abs());
+    }
+    // 归一化，安静的录音也能看清起伏
+    let max = peaks.iter().copied().fold(0., f32::max);
+    if max > 0. {
+        peaks.iter_mut().for_each(|x| *x /= max);
+    }
+    Some(peaks)
+}
+
+/// Format `secs` as "mm:ss" for the song list
+pub fn format_duration(secs: f32) -> SharedString {
+    format!("{:02}:{:02}", (secs as u32) / 60, (secs as u32) % 60).to_shared_string()
+}
+
+/// Songs per batch streamed by `scan_song_list`, at most this many files are read before their songs are handed on
+const SCAN_BATCH: usize = 200;
+
+/// Pool reading tags of scanned files, unset to use rayon's global pool (one thread per CPU core)
+static SCAN_POOL: OnceLock<ThreadPool> = OnceLock::new();
+
+/// Read tags of at most `threads` files at a time in scans, e.g. to spare a library on a spinning disk.
+/// 0 keeps one thread per CPU core
+pub fn set_scan_threads(threads: usize) {
+    if threads == 0 {
+        return;
+    }
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|idx| format!("scan-{}", idx))
+        .build();
+    match pool {
+        Ok(pool) => {
+            let _ = SCAN_POOL.set(pool);
+            log::info!("reading tags with {} threads", threads);
+        }
+        Err(e) => log::warn!("failed to create scan thread pool, using the default: {}", e),
     }
-    let glober = GlobBuilder::new("**/*.{mp3,flac,wav,ogg}").build().unwrap().compile_matcher();
-    let entries = WalkDir::new(audio_dir)
+}
+
+/// Run `f` in the scan pool, so the parallel iterators inside are bounded by its thread count
+fn in_scan_pool<R: Send>(f: impl FnOnce() -> R + Send) -> R {
+    match SCAN_POOL.get() {
+        Some(pool) => pool.install(f),
+        None => f(),
+    }
+}
+
+/// Audio files in all of `audio_dirs`. Directories that don't exist are skipped,
+/// and files reached through several of them (e.g. a directory nested in another) are listed once.
+fn audio_files(audio_dirs: &[PathBuf], cancel: &AtomicBool) -> Vec<walkdir::DirEntry> {
+    let glober = audio_glob();
+    let mut seen = HashSet::new();
+    audio_dirs
+        .iter()
+        .filter(|dir| {
+            let exists = dir.exists();
+            if !exists {
+                log::warn!("music directory {:?} not found, skipped", dir);
+            }
+            exists
+        })
+        .flat_map(WalkDir::new)
+        .take_while(|_| !cancel.load(Ordering::Relaxed))
+        .filter_map(|x| x.ok())
+        .filter(|x| glober.is_match(x.path()))
+        .filter(|x| seen.insert(x.path().to_path_buf()))
+        .collect()
+}
+
+/// Scan songs in `audio_dirs`, passing each batch read and the fraction of files done to `on_batch`.
+/// Only tags of files missing from `cache` or changed since are read,
+/// and entries under `audio_dirs` are updated to hold exactly the files found there.
+/// Entries of other folders are kept, so switching between libraries doesn't drop them.
+/// Return all songs found, or `None` if `cancel` was set before the scan finished, leaving `cache` untouched.
+pub fn scan_song_list(
+    audio_dirs: &[PathBuf],
+    cache: &mut LibraryCache,
+    cancel: &AtomicBool,
+    mut on_batch: impl FnMut(Vec<SongInfo>, f32),
+) -> Option<Vec<SongInfo>> {
+    let start = Instant::now();
+    let entries = audio_files(audio_dirs, cancel);
+    // 第二项为需要写回缓存的文件时间戳，命中缓存的歌曲为 None
+    let mut scanned = Vec::with_capacity(entries.len());
+    for (idx, chunk) in entries.chunks(SCAN_BATCH).enumerate() {
+        if cancel.load(Ordering::Relaxed) {
+            return None;
+        }
+        let cached = &*cache;
+        let batch = in_scan_pool(|| {
+            chunk
+                .par_iter()
+                .filter_map(|entry| {
+                    let stamp = library_cache::file_stamp(entry.path());
+                    let key = entry.path().display().to_string();
+                    match stamp.and_then(|stamp| cached.get(&key, stamp)) {
+                        Some(song) => Some((song, None)),
+                        None => read_meta_info(entry.path()).map(|song| (song, stamp)),
+                    }
+                })
+                .collect::<Vec<_>>()
+        });
+        let done = (idx * SCAN_BATCH + chunk.len()) as f32 / entries.len() as f32;
+        on_batch(batch.iter().map(|(x, _)| x.clone()).collect(), done);
+        scanned.extend(batch);
+    }
+    if cancel.load(Ordering::Relaxed) {
+        return None;
+    }
+    let present = scanned.iter().map(|(x, _)| x.song_path.to_string()).collect::<HashSet<_>>();
+    // 只清理本次扫描目录下已不存在的文件，其他曲库的缓存保留，切换回去时不必重新读取
+    cache.retain(|path| {
+        present.contains(path) || !audio_dirs.iter().any(|dir| Path::new(path).starts_with(dir))
+    });
+    let mut fresh = 0;
+    for (song, stamp) in scanned.iter() {
+        if let Some(stamp) = stamp {
+            cache.insert(song, *stamp);
+            fresh += 1;
+        }
+    }
+    let elapsed = start.elapsed();
+    log::info!(
+        "library scan: {} songs, {} read from tags in {:.1?} ({:.0} files/s)",
+        scanned.len(),
+        fresh,
+        elapsed,
+        scanned.len() as f64 / elapsed.as_secs_f64().max(0.001)
+    );
+    Some(scanned.into_iter().map(|(x, _)| x).collect())
+}
+
+/// Read songs directly inside `dir`, without descending into sub folders
+pub fn read_folder_songs(dir: impl AsRef<Path>) -> Vec<SongInfo> {
+    let glober = audio_glob();
+    let entries = WalkDir::new(dir)
+        .max_depth(1)
         .into_iter()
         .filter_map(|x| x.ok())
         .filter(|x| glober.is_match(x.path()))
         .collect::<Vec<_>>();
-    let mut songs = entries
-        .into_par_iter()
-        .map(|entry| read_meta_info(entry.path()))
-        .flatten()
+    in_scan_pool(|| {
+        entries.into_par_iter().filter_map(|entry| read_meta_info(entry.path())).collect()
+    })
+}
+
+/// Walk `audio_dirs` without reading tags, except for files added or modified after `since`
+/// and files missing from `known`, e.g. moved in with their old timestamps.
+/// Return paths of all audio files found, and SongInfo of the new/changed ones.
+pub fn scan_changed_songs(
+    audio_dirs: &[PathBuf],
+    since: SystemTime,
+    known: &HashSet<SharedString>,
+) -> (HashSet<SharedString>, Vec<SongInfo>) {
+    let entries = audio_files(audio_dirs, &AtomicBool::new(false));
+    let present = entries.iter().map(|x| x.path().display().to_shared_string()).collect();
+    let changed = in_scan_pool(|| {
+        entries
+            .into_par_iter()
+            .filter(|entry| {
+                if !known.contains(entry.path().display().to_string().as_str()) {
+                    return true;
+                }
+                // 拷贝进来的文件可能保留了旧的修改时间，所以同时参考创建时间
+                entry.metadata().is_ok_and(|meta| {
+                    let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    let created = meta.created().unwrap_or(SystemTime::UNIX_EPOCH);
+                    modified.max(created) > since
+                })
+            })
+            .map(|entry| read_meta_info(entry.path()))
+            .flatten()
+            .collect::<Vec<_>>()
+    });
+    (present, changed)
+}
+
+/// Seconds since unix epoch of `time`
+pub fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Default extensions of the audio files picked up by library scans, all decodable by rodio and taggable by lofty.
+/// Opus and WMA are left out since rodio has no decoder for them.
+pub const AUDIO_EXTENSIONS: &[&str] = &["mp3", "flac", "wav", "ogg", "m4a", "aac"];
+
+/// Matcher built from the configured formats, `None` until `set_audio_formats` is called
+static AUDIO_GLOB: RwLock<Option<GlobMatcher>> = RwLock::new(None);
+
+fn build_audio_glob<S: AsRef<str>>(extensions: &[S]) -> Option<GlobMatcher> {
+    let extensions = extensions.iter().map(|x| x.as_ref()).collect::<Vec<_>>();
+    let pattern = format!("**/*.{{{}}}", extensions.join(","));
+    let glob = GlobBuilder::new(&pattern).case_insensitive(true).build().ok()?;
+    Some(glob.compile_matcher())
+}
+
+fn audio_glob() -> GlobMatcher {
+    match AUDIO_GLOB.read().unwrap().as_ref() {
+        Some(glob) => glob.clone(),
+        None => build_audio_glob(AUDIO_EXTENSIONS).expect("invalid default audio glob"),
+    }
+}
+
+/// Whether `path` has one of the scanned audio extensions
+pub fn is_audio_file(path: impl AsRef<Path>) -> bool {
+    audio_glob().is_match(path)
+}
+
+/// Override the scanned extensions, e.g. from config.
+/// Entries like ".MP3" are normalized, an empty or unusable list falls back to `AUDIO_EXTENSIONS`.
+pub fn set_audio_formats(formats: &[String]) {
+    let formats = formats
+        .iter()
+        .map(|x| x.trim().trim_start_matches('.').to_lowercase())
+        .filter(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_alphanumeric()))
         .collect::<Vec<_>>();
-    if ascending {
-        songs.par_sort_by_key(|x| match sort_key {
-            SortKey::BySongName => x.song_name.clone(),
-            SortKey::BySinger => x.singer.clone(),
-            SortKey::ByDuration => x.duration.clone(),
-        });
+    let glob = if formats.is_empty() {
+        None
     } else {
-        songs.par_sort_by_key(|x| match sort_key {
-            SortKey::BySongName => std::cmp::Reverse(x.song_name.clone()),
-            SortKey::BySinger => std::cmp::Reverse(x.singer.clone()),
-            SortKey::ByDuration => std::cmp::Reverse(x.duration.clone()),
-        });
+        build_audio_glob(&formats)
+    };
+    if glob.is_none() {
+        log::warn!("no valid audio formats configured, using defaults: {:?}", AUDIO_EXTENSIONS);
+    } else {
+        log::info!("scanning audio formats: {:?}", formats);
     }
+    *AUDIO_GLOB.write().unwrap() = glob;
+}
+
+/// Folders holding `songs` and their parents up to the deepest common one, each followed by its subfolders
+/// in name order and counting the songs of its whole subtree
+pub fn folder_tree(songs: &[SongInfo]) -> Vec<FolderItem> {
+    let dirs =
+        songs.iter().filter_map(|x| Path::new(x.song_path.as_str()).parent()).collect::<Vec<_>>();
+    let Some(first) = dirs.first() else {
+        return Vec::new();
+    };
+    let root = dirs.iter().fold(*first, |common, dir| {
+        common.ancestors().find(|x| dir.starts_with(x)).unwrap_or(Path::new(""))
+    });
+    // PathBuf 按路径组件比较，子文件夹紧跟在父文件夹之后
+    let mut counts = BTreeMap::<PathBuf, i32>::new();
+    for dir in &dirs {
+        for folder in dir.ancestors().take_while(|x| x.starts_with(root)) {
+            *counts.entry(folder.to_path_buf()).or_default() += 1;
+        }
+    }
+    let root_depth = root.components().count();
+    counts
+        .into_iter()
+        .map(|(path, song_count)| FolderItem {
+            name: path
+                .file_name()
+                .map_or_else(|| path.display().to_string(), |x| x.to_string_lossy().into())
+                .into(),
+            depth: (path.components().count() - root_depth) as i32,
+            path: path.display().to_string().into(),
+            song_count,
+        })
+        .collect()
+}
+
+/// Songs of `library` whose name, singer or album fuzzily match `query`, ignoring case, best matches first.
+/// Songs of equal score keep their order, and an empty query keeps every song.
+/// With `search_lyrics`, songs whose indexed lyrics contain `query` match too, after the fuzzy matches.
+pub fn filter_songs(
+    library: impl Iterator<Item = SongInfo>,
+    query: &str,
+    search_lyrics: bool,
+) -> Vec<SongInfo> {
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return library.collect();
+    }
+    let matcher = SkimMatcherV2::default().ignore_case();
+    let lyrics_index = LYRICS_INDEX.read().unwrap();
+    let lyrics_index = lyrics_index.as_ref().filter(|_| search_lyrics);
+    let mut scored = library
+        .filter_map(|x| {
+            let score = [&x.song_name, &x.singer, &x.album]
+                .into_iter()
+                .filter_map(|field| matcher.fuzzy_match(field, &query))
+                .max();
+            // 歌词只做子串匹配，逐字模糊匹配几乎能命中任何一段长文本
+            let in_lyrics = || {
+                lyrics_index
+                    .and_then(|index| index.get(x.song_path.as_str()))
+                    .is_some_and(|lyrics| lyrics.contains(&query))
+            };
+            score.or_else(|| in_lyrics().then_some(i64::MIN)).map(|score| (score, x))
+        })
+        .collect::<Vec<_>>();
+    scored.sort_by_key(|x| std::cmp::Reverse(x.0));
+    scored.into_iter().map(|(_, x)| x).collect()
+}
+
+/// Lowercased lyrics (with translations) of songs by path, for lyric search.
+/// `None` until lyric search is first turned on, so scans don't read lyrics nobody searches
+static LYRICS_INDEX: RwLock<Option<BTreeMap<String, String>>> = RwLock::new(None);
+
+/// Start keeping the lyrics index, songs are added to it by `index_lyrics`
+pub fn enable_lyrics_index() {
+    LYRICS_INDEX.write().unwrap().get_or_insert_default();
+}
+
+/// Read the lyrics of `songs` missing from the lyrics index in parallel, if the index is enabled.
+/// Songs without lyrics get an empty entry, so they aren't read again.
+pub fn index_lyrics(songs: &[SongInfo]) {
+    let missing = match LYRICS_INDEX.read().unwrap().as_ref() {
+        Some(index) => songs
+            .iter()
+            .map(|x| x.song_path.as_str())
+            .filter(|x| !index.contains_key(*x) && !stream::is_url(x))
+            .map(String::from)
+            .collect::<Vec<_>>(),
+        None => return,
+    };
+    if missing.is_empty() {
+        return;
+    }
+    let entries = in_scan_pool(|| {
+        missing
+            .into_par_iter()
+            .map(|path| {
+                let lyrics = read_lyrics(&path)
+                    .iter()
+                    .flat_map(|x| [x.text.to_lowercase(), x.translation.to_lowercase()])
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                (path, lyrics)
+            })
+            .collect::<Vec<_>>()
+    });
+    log::info!("indexed lyrics of {} songs", entries.len());
+    if let Some(index) = LYRICS_INDEX.write().unwrap().as_mut() {
+        index.extend(entries);
+    }
+}
+
+/// Drop `songs` from the lyrics index, e.g. after their files changed
+pub fn forget_lyrics(songs: &[SongInfo]) {
+    if let Some(index) = LYRICS_INDEX.write().unwrap().as_mut() {
+        for song in songs {
+            index.remove(song.song_path.as_str());
+        }
+    }
+}
+
+/// Group `songs` by album or artist, groups ordered by name ignoring case.
+/// `songs` is reordered group by group and renumbered, album groups by track number, others keeping their order.
+/// Returns no groups and leaves `songs` untouched with `SongGrouping::None`.
+pub fn group_songs(songs: &mut Vec<SongInfo>, grouping: SongGrouping) -> Vec<SongGroup> {
+    let key = |x: &SongInfo| match grouping {
+        SongGrouping::Album => x.album.clone(),
+        SongGrouping::Artist => x.singer.clone(),
+        SongGrouping::None => SharedString::new(),
+    };
+    if grouping == SongGrouping::None {
+        return Vec::new();
+    }
+    let mut groups = BTreeMap::<String, (SharedString, Vec<SongInfo>)>::new();
+    for song in songs.drain(..) {
+        let name = key(&song);
+        groups.entry(name.to_lowercase()).or_insert_with(|| (name, Vec::new())).1.push(song);
+    }
+    groups
+        .into_values()
+        .map(|(name, mut group)| {
+            if grouping == SongGrouping::Album {
+                group.sort_by_key(|x| x.track_number);
+            }
+            for song in group.iter_mut() {
+                song.id = songs.len() as i32;
+                songs.push(song.clone());
+            }
+            SongGroup {
+                name,
+                songs: group.as_slice().into(),
+            }
+        })
+        .collect()
+}
+
+/// Sort `songs` by `sort_key` and renumber their ids by the new order
+pub fn sort_songs(songs: &mut [SongInfo], sort_key: SortKey, ascending: bool) {
+    songs.par_sort_by(|a, b| {
+        let order = match sort_key {
+            SortKey::BySongName => a.song_name.cmp(&b.song_name),
+            SortKey::BySinger => a.singer.cmp(&b.singer),
+            // 按秒数比较，避免 "10:00" 的字符串排在 "9:30" 前面
+            SortKey::ByDuration => a.duration_secs.total_cmp(&b.duration_secs),
+            SortKey::ByYear => a.year.cmp(&b.year),
+            // 同一专辑内按音轨号排列
+            SortKey::ByAlbum => a.album.cmp(&b.album).then(a.track_number.cmp(&b.track_number)),
+            SortKey::ByDateAdded => a.date_added.cmp(&b.date_added),
+        };
+        if ascending {
+            order
+        } else {
+            order.reverse()
+        }
+    });
+    songs.iter_mut().enumerate().for_each(|(idx, x)| x.id = idx as i32);
+}
+
+/// Write `songs` to `path` as an extended M3U playlist
+pub fn save_playlist(path: impl AsRef<Path>, songs: &[SongInfo]) -> io::Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(file, "#EXTM3U")?;
+    for song in songs {
+        writeln!(
+            file,
+            "#EXTINF:{},{} - {}",
+            song.duration_secs as u32, song.singer, song.song_name
+        )?;
+        writeln!(file, "{}", song.song_path)?;
+    }
+    file.flush()
+}
+
+/// Read songs of an M3U/M3U8 playlist, relative paths are resolved against the playlist directory.
+/// Entries whose file is missing or unreadable are skipped with a warning.
+pub fn load_playlist(path: impl AsRef<Path>) -> io::Result<Vec<SongInfo>> {
+    let entries = playlist::read_playlist(path)?;
+    let songs = in_scan_pool(|| {
+        entries
+            .into_par_iter()
+            .filter_map(|entry| {
+                let song = read_meta_info(&entry.path);
+                if song.is_none() {
+                    log::warn!("playlist entry is missing or unreadable: <{}>", entry.path);
+                }
+                song
+            })
+            .collect()
+    });
+    Ok(songs)
+}
+
+/// Names of the playlists saved in `dir`, without extension and sorted
+pub fn list_playlists(dir: impl AsRef<Path>) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut names = entries
+        .filter_map(|x| x.ok())
+        .map(|x| x.path())
+        .filter(|x| {
+            x.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| {
+                ext.eq_ignore_ascii_case("m3u8") || ext.eq_ignore_ascii_case("m3u")
+            })
+        })
+        .filter_map(|x| x.file_stem().and_then(|x| x.to_str()).map(String::from))
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Deduplicate play history, keeping only the most recent play of each song (oldest first)
+pub fn dedup_history(history: &[SongInfo]) -> Vec<SongInfo> {
+    let mut seen = HashSet::new();
+    let mut songs = history
+        .iter()
+        .rev()
+        .filter(|song| seen.insert(song.song_path.clone()))
+        .cloned()
+        .collect::<Vec<_>>();
+    songs.reverse();
     songs
-        .into_par_iter()
+}
+
+/// Pick the next song for artist radio from `library`, return its index.
+/// Candidates are weighted by sharing the artist or genre of `seed`, and songs in `recent` are skipped
+/// unless nothing else is left. Falls back to a uniform pick when every weight is zero.
+pub fn pick_radio_song(
+    library: &[SongInfo],
+    seed: &SongInfo,
+    recent: &[SharedString],
+    weights: &RadioWeights,
+) -> Option<usize> {
+    let mut candidates = library
+        .iter()
         .enumerate()
-        .map(|(idx, mut x)| {
-            x.id = idx as i32;
-            x
-        })
-        .collect::<Vec<_>>()
+        .filter(|(_, song)| !song.skip_autoplay && !recent.contains(&song.song_path))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        candidates = library.iter().enumerate().filter(|(_, song)| !song.skip_autoplay).collect();
+    }
+    let weight_of = |song: &SongInfo| {
+        if seed.singer != "unknown" && song.singer == seed.singer {
+            weights.same_artist
+        } else if seed.genre != "unknown" && song.genre == seed.genre {
+            weights.same_genre
+        } else {
+            weights.other
+        }
+    };
+    let mut rng = rand::rng();
+    match WeightedIndex::new(candidates.iter().map(|(_, song)| weight_of(song).max(0.))) {
+        Ok(dist) => Some(candidates[dist.sample(&mut rng)].0),
+        Err(_) => candidates.choose(&mut rng).map(|(idx, _)| *idx),
+    }
+}
+
+/// Index of `song` in `songs`, found by path since its id may come from a list sorted differently.
+/// `None` if the list doesn't contain it, e.g. it is hidden by a filter or was opened from a URL
+pub fn song_index(songs: &[SongInfo], song: &SongInfo) -> Option<usize> {
+    songs.iter().position(|x| x.song_path == song.song_path)
+}
+
+/// Get index of the first song after `id` not flagged as skipped in autoplay, wrapping around,
+/// or of the first one from the top if `id` is `None`. Returns `None` if every song is flagged.
+pub fn next_playable(songs: &[SongInfo], id: Option<usize>) -> Option<usize> {
+    let start = id.map_or(0, |x| x + 1);
+    (0..songs.len()).map(|step| (start + step) % songs.len()).find(|&i| !songs[i].skip_autoplay)
 }
 
-/// Read lyrics from audio file `p`, return a list of LyricItem
-pub fn read_lyrics(path: impl AsRef<Path>) -> Vec<LyricItem> {
+/// Read lyrics of audio file `p`, return a list of timed lines.
+/// Embedded lyrics take precedence, otherwise a sibling `.lrc` file with the same stem is used.
+/// Lyrics without translations are paired with a translation file like `song.zh.lrc` when there is one.
+pub fn read_lyrics(path: impl AsRef<Path>) -> Vec<Lyric> {
     let path = path.as_ref();
-    if let Ok(tagged) = lofty::read_from_path(path)
-        && let Some(tag) = tagged.primary_tag()
-        && let Some(lyric_item) = tag.get(&ItemKey::Lyrics)
+    let embedded = lofty::read_from_path(path).ok().and_then(|tagged| {
+        let text = tagged.primary_tag()?.get_string(&ItemKey::Lyrics)?;
+        Some(parse_lrc(text).0).filter(|x| !x.is_empty())
+    });
+    let mut lyrics =
+        embedded.or_else(|| read_lrc_file(path).map(|text| parse_lrc(&text).0)).unwrap_or_default();
+    if !lyrics.is_empty()
+        && lyrics.iter().all(|x| x.translation.is_empty())
+        && let Some(text) = read_translation_file(path)
     {
-        let mut lyrics = lyric_item
-            .value()
-            .text()
-            .unwrap()
-            .split("\n")
-            .map(|line| {
-                let (time_str, text) = line.split_once(']').unwrap_or(("", ""));
-                let time_str = time_str.trim_start_matches('[');
-                let dura = time_str
-                    .split(':')
-                    .map(|x| x.parse::<f32>().unwrap_or(0.))
-                    .rev()
-                    .reduce(|acc, x| acc + x * 60.)
-                    .unwrap_or(0.);
-                LyricItem {
-                    time: dura,
-                    text: text.to_shared_string(),
-                    duration: 0.0,
+        add_translation(&mut lyrics, &parse_lrc(&text).0);
+    }
+    lyrics
+}
+
+/// Read the `.lrc` file next to audio file `path`, if any
+fn read_lrc_file(path: &Path) -> Option<String> {
+    let bytes =
+        ["lrc", "LRC"].iter().find_map(|ext| std::fs::read(path.with_extension(ext)).ok())?;
+    Some(decode_lrc(&bytes))
+}
+
+/// Read a translation `.lrc` file next to audio file `path`, named like `song.zh.lrc`
+fn read_translation_file(path: &Path) -> Option<String> {
+    let prefix = format!("{}.", path.file_stem()?.to_str()?.to_lowercase());
+    let mut files = std::fs::read_dir(path.parent()?)
+        .ok()?
+        .flatten()
+        .map(|x| x.path())
+        .filter(|x| {
+            let name = x.file_name().and_then(|x| x.to_str()).unwrap_or_default().to_lowercase();
+            // 排除与歌曲同名的 song.lrc 本身
+            name.starts_with(&prefix) && name.ends_with(".lrc") && name.len() > prefix.len() + 4
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    let bytes = files.iter().find_map(|x| std::fs::read(x).ok())?;
+    Some(decode_lrc(&bytes))
+}
+
+/// Text of a LRC file, which may start with a BOM or not be valid UTF-8
+fn decode_lrc(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_start_matches('\u{feff}').to_string()
+}
+
+/// Largest time difference between a lyric line and its translation from another file, in seconds
+const TRANSLATION_TOLERANCE_SECS: f32 = 0.1;
+
+/// Attach lines of `translation` to the `lyrics` lines at the same time
+fn add_translation(lyrics: &mut [Lyric], translation: &[Lyric]) {
+    for item in lyrics.iter_mut() {
+        if let Some(line) =
+            translation.iter().find(|x| (x.time - item.time).abs() <= TRANSLATION_TOLERANCE_SECS)
+        {
+            item.translation = line.text.clone();
+        }
+    }
+}
+
+/// Metadata tags of a LRC file, like `[ti:Title]`
+#[derive(Clone, Debug, Default)]
+pub struct LrcMeta {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// `[offset:+250]` in milliseconds, positive values make lyrics show earlier
+    pub offset_ms: i64,
+}
+
+/// A timed lyric line, turned into a LyricItem on the UI thread since its word model can't cross threads
+#[derive(Clone, Debug, Default)]
+pub struct Lyric {
+    pub time: f32,
+    pub duration: f32,
+    pub text: SharedString,
+    /// Per-word timing of enhanced LRC, empty for plain lines
+    pub words: Vec<LyricWord>,
+    /// Translated line shown beneath, empty when there is none
+    pub translation: SharedString,
+}
+
+impl From<&Lyric> for LyricItem {
+    fn from(lyric: &Lyric) -> Self {
+        LyricItem {
+            time: lyric.time,
+            duration: lyric.duration,
+            text: lyric.text.clone(),
+            words: lyric.words.as_slice().into(),
+            translation: lyric.translation.clone(),
+        }
+    }
+}
+
+/// Parse LRC `text` into timed lyric lines sorted by time, and its metadata tags.
+/// A line may carry several timestamps (`[00:12.00][00:45.00]text`), it is repeated at each of them.
+/// Enhanced LRC word timestamps (`<00:12.50>word`) are kept as per-word timing of the line,
+/// a second line at the same time as another one is taken as its translation.
+/// The offset tag is applied to every lyric time, unknown tags like `[by:]` are skipped.
+pub fn parse_lrc(text: &str) -> (Vec<Lyric>, LrcMeta) {
+    let mut meta = LrcMeta::default();
+    let mut lyrics = Vec::new();
+    for line in text.lines() {
+        let mut rest = line.trim();
+        let mut times = Vec::new();
+        while let Some((tag, text)) = rest.strip_prefix('[').and_then(|x| x.split_once(']')) {
+            rest = text;
+            // 时间戳以数字开头，其余的 [key:value] 为元数据
+            if !tag.starts_with(|c: char| c.is_ascii_digit()) {
+                if let Some((key, value)) = tag.split_once(':') {
+                    let value = value.trim().to_string();
+                    match key.trim().to_ascii_lowercase().as_str() {
+                        "ti" => meta.title = Some(value),
+                        "ar" => meta.artist = Some(value),
+                        "al" => meta.album = Some(value),
+                        "offset" => {
+                            meta.offset_ms = value.trim_start_matches('+').parse().unwrap_or(0)
+                        }
+                        _ => {}
+                    }
                 }
-            })
-            .filter(|ins| ins.time > 0. && !ins.text.is_empty())
-            .collect::<Vec<_>>();
-        for i in 0..lyrics.len() - 1 {
-            lyrics[i].duration = lyrics[i + 1].time - lyrics[i].time;
+                continue;
+            }
+            // 格式错误的时间戳直接忽略
+            times.extend(lrc_time(tag));
+        }
+        let (text, words) = parse_lrc_words(rest);
+        let Some(&line_time) = times.first() else {
+            continue;
+        };
+        for time in times {
+            if time > 0. && !text.is_empty() {
+                // 逐字时间相对于行首，同一行在多个时间点重复时也能共用
+                let words: Vec<_> = words.iter().map(|(t, x)| (t - line_time, x.clone())).collect();
+                lyrics.push((
+                    Lyric {
+                        time,
+                        text: text.to_shared_string(),
+                        duration: 0.0,
+                        words: Vec::new(),
+                        translation: SharedString::new(),
+                    },
+                    words,
+                ));
+            }
         }
-        if let Some(ins) = lyrics.last_mut() {
-            ins.duration = 100.0;
+    }
+    let offset = meta.offset_ms as f32 / 1000.;
+    for (item, _) in lyrics.iter_mut() {
+        item.time = (item.time - offset).max(0.);
+    }
+    lyrics.sort_by(|a, b| a.0.time.total_cmp(&b.0.time));
+    // 排序是稳定的，同一时间的第二行按文件顺序排在原文之后
+    lyrics.dedup_by(|next, prev| {
+        let translated = next.0.time == prev.0.time && prev.0.translation.is_empty();
+        if translated {
+            prev.0.translation = next.0.text.clone();
         }
-        return lyrics;
+        translated
+    });
+    for i in 1..lyrics.len() {
+        lyrics[i - 1].0.duration = lyrics[i].0.time - lyrics[i - 1].0.time;
+    }
+    if let Some((ins, _)) = lyrics.last_mut() {
+        ins.duration = 100.0;
     }
-    Vec::new()
+    let lyrics = lyrics
+        .into_iter()
+        .map(|(mut item, words)| {
+            item.words = lyric_words(&words, item.duration);
+            item
+        })
+        .collect();
+    (lyrics, meta)
 }
 
-/// Read album cover from audio file `p`, return a slint::Image
+/// Longest a word without an end timestamp stays highlighting, in seconds
+const LAST_WORD_MAX_SECS: f32 = 2.0;
+
+/// Seconds of a LRC timestamp like `mm:ss.xx` or `hh:mm:ss.xx`, None if it is malformed
+fn lrc_time(tag: &str) -> Option<f32> {
+    let is_number = |x: &str| !x.is_empty() && x.bytes().all(|b| b.is_ascii_digit());
+    let (rest, secs) = tag.trim().rsplit_once(':')?;
+    let (secs, frac) = secs.split_once('.').unwrap_or((secs, ""));
+    let (hours, minutes) = rest.split_once(':').unwrap_or(("0", rest));
+    if ![hours, minutes, secs].into_iter().all(is_number) || !(frac.is_empty() || is_number(frac)) {
+        return None;
+    }
+    let (hours, minutes, secs) =
+        (hours.parse::<u64>().ok()?, minutes.parse::<u64>().ok()?, secs.parse::<u64>().ok()?);
+    // 只有带小时时分钟才限制在 60 以内，[75:00.00] 这样的写法也是常见的
+    if secs >= 60 || (rest.contains(':') && minutes >= 60) {
+        return None;
+    }
+    // 小数部分换算为毫秒，多余的位数舍去，整数运算避免浮点误差
+    let millis = format!("{:0<3}", &frac[..frac.len().min(3)]).parse::<u64>().ok()?;
+    let total = ((hours * 60 + minutes) * 60 + secs) * 1000 + millis;
+    Some(total as f32 / 1000.)
+}
+
+/// Split the text of a LRC line by its `<mm:ss.xx>` word timestamps.
+/// Return the text without timestamps and each timed piece, or no pieces if the line has no timestamps.
+/// A trailing timestamp without text marks the end of the last word.
+fn parse_lrc_words(line: &str) -> (String, Vec<(f32, String)>) {
+    let mut text = String::new();
+    let mut words = Vec::new();
+    let mut rest = line;
+    let mut time = None;
+    loop {
+        let next = rest.find('<').and_then(|start| {
+            let (tag, after) = rest[start + 1..].split_once('>')?;
+            Some((start, lrc_time(tag)?, after))
+        });
+        let piece = next.map_or(rest, |(start, _, _)| &rest[..start]);
+        text.push_str(piece);
+        match time {
+            Some(t) => words.push((t, piece.to_string())),
+            // 第一个时间戳之前的文字属于行首
+            None if !piece.trim().is_empty() => words.push((f32::NEG_INFINITY, piece.to_string())),
+            None => {}
+        }
+        let Some((_, tag_time, after)) = next else {
+            break;
+        };
+        time = Some(tag_time);
+        rest = after;
+    }
+    if time.is_none() {
+        words.clear();
+    }
+    (text.trim().to_string(), words)
+}
+
+/// Turn timed pieces of a line into LyricWords, `line_duration` bounds the last word
+fn lyric_words(pieces: &[(f32, String)], line_duration: f32) -> Vec<LyricWord> {
+    let mut words = Vec::new();
+    for (i, (time, text)) in pieces.iter().enumerate() {
+        let time = time.max(0.);
+        let end = match pieces.get(i + 1) {
+            Some((next, _)) => next.max(time),
+            None => time + (line_duration - time).clamp(0., LAST_WORD_MAX_SECS),
+        };
+        // 空的片段只用于标记上一个字的结束时间
+        if !text.is_empty() {
+            words.push(LyricWord {
+                time,
+                duration: end - time,
+                text: text.to_shared_string(),
+            });
+        }
+    }
+    words
+}
+
+/// Standalone cover image names (file stem, case insensitive) next to audio files, by preference
+const FOLDER_COVER_NAMES: [&str; 3] = ["cover", "folder", "front"];
+
+/// Extensions of standalone cover images
+const FOLDER_COVER_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "bmp", "webp"];
+
+/// Read the cover of audio file `path`, return RGBA buffer with its width and height.
+/// The best embedded picture is used, or else a `cover.*`, `folder.*` or `front.*` image in the file's folder.
 pub fn read_album_cover(path: impl AsRef<Path>) -> Option<(Vec<u8>, u32, u32)> {
     let path = path.as_ref();
-    if let Ok(tagged) = lofty::read_from_path(path)
-        && let Some(tag) = tagged.primary_tag()
-        && let Some(picture) = tag.pictures().iter().find(|pic| {
-            pic.pic_type() == PictureType::CoverFront || pic.pic_type() == PictureType::CoverBack
+    read_embedded_cover(path).or_else(|| read_folder_cover(path.parent()?))
+}
+
+/// Read the best embedded cover of audio file `path`
+fn read_embedded_cover(path: &Path) -> Option<(Vec<u8>, u32, u32)> {
+    let tagged = lofty::read_from_path(path).ok()?;
+    // 只读取图片头部的尺寸来排序候选，优先封面正面，其次像素数，最后字节数
+    let mut candidates = tagged
+        .tags()
+        .iter()
+        .flat_map(|tag| tag.pictures())
+        .map(|pic| {
+            let area = image::ImageReader::new(Cursor::new(pic.data()))
+                .with_guessed_format()
+                .ok()
+                .and_then(|reader| reader.into_dimensions().ok())
+                .map(|(w, h)| w as u64 * h as u64)
+                .unwrap_or(0);
+            ((pic.pic_type() == PictureType::CoverFront, area, pic.data().len()), pic)
         })
-        && let Ok(img) = image::load_from_memory(picture.data())
-    {
-        let rgba = img.into_rgba8();
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|(rank, _)| std::cmp::Reverse(*rank));
+    // 只完整解码最优的候选，解码失败时才尝试下一个
+    candidates.into_iter().find_map(|(_, pic)| {
+        let rgba = image::load_from_memory(pic.data()).ok()?.into_rgba8();
+        let (width, height) = rgba.dimensions();
+        Some((rgba.into_vec(), width, height))
+    })
+}
+
+/// Read a standalone cover image in folder `dir`, e.g. `cover.jpg` or `Folder.png`
+fn read_folder_cover(dir: &Path) -> Option<(Vec<u8>, u32, u32)> {
+    let mut candidates = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|x| x.ok())
+        .map(|x| x.path())
+        .filter_map(|path| {
+            let stem = path.file_stem()?.to_str()?.to_lowercase();
+            let ext = path.extension()?.to_str()?.to_lowercase();
+            let rank = FOLDER_COVER_NAMES.iter().position(|x| *x == stem)?;
+            FOLDER_COVER_EXTENSIONS.contains(&ext.as_str()).then_some((rank, path))
+        })
+        .collect::<Vec<_>>();
+    // 同名的多张图片按文件名排序，保证每次选中同一张
+    candidates.sort();
+    candidates.into_iter().find_map(|(_, path)| {
+        let rgba = image::open(&path)
+            .inspect_err(|e| log::warn!("failed to read folder cover {:?}: {}", path, e))
+            .ok()?
+            .into_rgba8();
         let (width, height) = rgba.dimensions();
-        let buffer = rgba.into_vec();
-        return Some((buffer, width, height));
+        Some((rgba.into_vec(), width, height))
+    })
+}
+
+/// Downscale RGBA `buffer` so that its longest edge fits in `max_size`, keeping aspect ratio.
+/// Lanczos3 keeps covers sharp when shrinking a lot, e.g. 3000px art down to a thumbnail.
+pub fn downscale_cover(
+    buffer: Vec<u8>,
+    width: u32,
+    height: u32,
+    max_size: u32,
+) -> (Vec<u8>, u32, u32) {
+    if width <= max_size && height <= max_size {
+        return (buffer, width, height);
     }
-    None
+    let img =
+        image::RgbaImage::from_raw(width, height, buffer).expect("cover buffer size mismatch");
+    let resized = image::DynamicImage::ImageRgba8(img)
+        .resize(max_size, max_size, image::imageops::FilterType::Lanczos3)
+        .into_rgba8();
+    let (width, height) = resized.dimensions();
+    (resized.into_vec(), width, height)
 }
 
-pub fn from_image_to_slint(buffer: Vec<u8>, width: u32, height: u32) -> slint::Image {
+/// Override the max texture size, e.g. from config
+pub fn set_max_texture_size(size: u32) {
+    MAX_TEXTURE_SIZE.store(size.max(64), Ordering::Relaxed);
+}
+
+/// Convert RGBA `buffer` to a slint pixel buffer, capped to the max texture size
+pub fn to_pixel_buffer(
+    buffer: Vec<u8>,
+    width: u32,
+    height: u32,
+) -> slint::SharedPixelBuffer<slint::Rgba8Pixel> {
+    let max_size = MAX_TEXTURE_SIZE.load(Ordering::Relaxed);
+    if width > max_size || height > max_size {
+        log::info!("cover of {}x{} exceeds texture limit {}, downscaling", width, height, max_size);
+    }
+    let (buffer, width, height) = downscale_cover(buffer, width, height, max_size);
     let mut pixel_buffer = slint::SharedPixelBuffer::new(width, height);
     let pixel_buffer_data = pixel_buffer.make_mut_bytes();
     pixel_buffer_data.copy_from_slice(&buffer);
-    slint::Image::from_rgba8(pixel_buffer)
+    pixel_buffer
 }
 
 pub fn get_default_album_cover() -> slint::Image {
@@ -158,11 +1086,185 @@ pub fn get_default_album_cover() -> slint::Image {
 /// Get about info string
 pub fn get_about_info() -> SharedString {
     format!(
-        "{}\n{}\nAuthor: {}\nVersion: {}",
+        "{}\n{}\nAuthor: {}\nVersion: {}\nLog file: {}",
         env!("CARGO_PKG_NAME"),
         env!("CARGO_PKG_DESCRIPTION"),
         env!("CARGO_PKG_AUTHORS"),
-        env!("CARGO_PKG_VERSION")
+        env!("CARGO_PKG_VERSION"),
+        logger::log_path().display()
     )
     .into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(path: &str) -> SongInfo {
+        SongInfo {
+            song_path: path.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn next_after_failed_song_moves_past_it() {
+        let songs = [song("a.mp3"), song("b.mp3"), song("c.mp3")];
+        // b 打开失败后以 b 为起点，下一首是 c 而不是再次选中 b
+        let failed = song("b.mp3");
+        assert_eq!(next_playable(&songs, song_index(&songs, &failed)), Some(2));
+        let last = song("c.mp3");
+        assert_eq!(next_playable(&songs, song_index(&songs, &last)), Some(0));
+    }
+
+    #[test]
+    fn next_after_song_missing_from_list_starts_at_top() {
+        let mut songs = [song("a.mp3"), song("b.mp3"), song("c.mp3")];
+        // 被筛选隐藏的歌曲或重置后 id 为 -1 的空歌曲都不在列表中
+        let hidden = SongInfo {
+            id: 2,
+            ..song("hidden.mp3")
+        };
+        let reset = SongInfo {
+            id: -1,
+            ..Default::default()
+        };
+        assert_eq!(song_index(&songs, &hidden), None);
+        assert_eq!(next_playable(&songs, song_index(&songs, &hidden)), Some(0));
+        assert_eq!(next_playable(&songs, song_index(&songs, &reset)), Some(0));
+        songs[0].skip_autoplay = true;
+        assert_eq!(next_playable(&songs, None), Some(1));
+    }
+
+    #[test]
+    fn parse_lrc_reads_metadata_and_offset() {
+        let text =
+            "[ti:Song]\n[ar:Singer]\n[offset:+500]\n\n[00:01.00]first\n   \n[00:03.00]second\n";
+        let (lyrics, meta) = parse_lrc(text);
+        assert_eq!(meta.title.as_deref(), Some("Song"));
+        assert_eq!(meta.artist.as_deref(), Some("Singer"));
+        assert_eq!(meta.offset_ms, 500);
+        // 元数据行和空行不产生歌词，正的 offset 让歌词提前显示
+        let lines = lyrics.iter().map(|x| (x.time, x.text.as_str())).collect::<Vec<_>>();
+        assert_eq!(lines, [(0.5, "first"), (2.5, "second")]);
+    }
+
+    #[test]
+    fn parse_lrc_negative_offset_delays_lyrics() {
+        let (lyrics, meta) = parse_lrc("[offset:-1000]\n[00:01.00]line\n");
+        assert_eq!(meta.offset_ms, -1000);
+        assert_eq!(lyrics.len(), 1);
+        assert_eq!(lyrics[0].time, 2.0);
+    }
+
+    #[test]
+    fn lrc_time_parses_timestamps() {
+        let close = |x: Option<f32>, expected: f32| x.is_some_and(|x| (x - expected).abs() < 1e-3);
+        assert!(close(lrc_time("01:02:03.45"), 3723.45));
+        assert!(close(lrc_time("00:05.5"), 5.5));
+        assert_eq!(lrc_time("garbage"), None);
+        assert_eq!(lrc_time("00:60.00"), None);
+    }
+
+    /// Empty directory for one test under the system temp dir, removed first if a previous run left it
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("zeedle-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write a tagless 8 kHz mono 16-bit WAV of `secs` seconds of silence
+    fn write_wav(path: &Path, secs: u32) {
+        let data_len = 8000 * 2 * secs;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&1u16.to_le_bytes()); // 单声道
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&16000u32.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        wav.resize(wav.len() + data_len as usize, 0);
+        std::fs::write(path, wav).unwrap();
+    }
+
+    #[test]
+    fn scan_lists_tagless_file_by_name() {
+        let dir = test_dir("tagless");
+        write_wav(&dir.join("Untitled Track.wav"), 1);
+        let songs = scan_song_list(
+            std::slice::from_ref(&dir),
+            &mut LibraryCache::default(),
+            &AtomicBool::new(false),
+            |_, _| {},
+        )
+        .unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(songs.len(), 1);
+        assert_eq!(songs[0].song_name, "Untitled Track");
+        assert_eq!(songs[0].singer, "unknown");
+        assert!((songs[0].duration_secs - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn m4a_files_are_scanned() {
+        assert!(is_audio_file("x.M4A"));
+        assert!(!is_audio_file("x.txt"));
+        let dir = test_dir("m4a");
+        std::fs::write(dir.join("song.m4a"), b"").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"").unwrap();
+        let found = audio_files(std::slice::from_ref(&dir), &AtomicBool::new(false))
+            .iter()
+            .map(|x| x.file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(found, ["song.m4a"]);
+    }
+
+    #[test]
+    fn folder_cover_is_used_without_embedded_art() {
+        let dir = test_dir("folder-cover");
+        write_wav(&dir.join("song.wav"), 1);
+        image::RgbaImage::new(3, 2).save(dir.join("Folder.png")).unwrap();
+        let cover = read_album_cover(dir.join("song.wav")).map(|(_, w, h)| (w, h));
+        assert_eq!(cover, Some((3, 2)));
+        // cover.* 优先于 folder.*
+        image::RgbaImage::new(4, 4).save(dir.join("cover.png")).unwrap();
+        let cover = read_album_cover(dir.join("song.wav")).map(|(_, w, h)| (w, h));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(cover, Some((4, 4)));
+    }
+
+    #[test]
+    fn scan_keeps_cache_of_other_folders() {
+        let dir = test_dir("other-folders");
+        let song_path = dir.join("kept.wav");
+        write_wav(&song_path, 1);
+        let stamp = library_cache::file_stamp(&song_path).unwrap();
+        let mut cache = LibraryCache::default();
+        let cached = |path: &Path| SongInfo {
+            song_path: path.display().to_string().into(),
+            ..Default::default()
+        };
+        cache.insert(&cached(&song_path), stamp);
+        cache.insert(&cached(&dir.join("deleted.wav")), (0, 0));
+        cache.insert(&cached(Path::new("/other library/song.mp3")), (0, 0));
+        let songs = scan_song_list(
+            std::slice::from_ref(&dir),
+            &mut cache,
+            &AtomicBool::new(false),
+            |_, _| {},
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(songs.map(|x| x.len()), Some(1));
+        // 扫描目录下已删除的文件被清理，其他曲库的不受影响
+        let kept = cache.songs.keys().cloned().collect::<Vec<_>>();
+        assert_eq!(kept, ["/other library/song.mp3".to_string(), song_path.display().to_string()]);
+    }
+}
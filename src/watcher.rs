@@ -0,0 +1,57 @@
+use std::{path::PathBuf, sync::mpsc, thread, time::Duration};
+
+use notify::{
+    Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+    event::{CreateKind, ModifyKind, RemoveKind},
+};
+
+use crate::utils;
+
+/// Changes are reported once the directories stay quiet this long, so copying an album triggers one refresh
+const WATCH_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Watch `dirs` recursively on a background thread, calling `on_change` after audio files
+/// (or whole folders) were created, deleted or renamed. Directories that can't be watched are skipped.
+/// Watching stops when the returned watcher is dropped.
+pub fn watch_song_dirs(
+    dirs: &[PathBuf],
+    on_change: impl Fn() + Send + 'static,
+) -> Option<RecommendedWatcher> {
+    let (tx, rx) = mpsc::channel::<()>();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+            Ok(event) if is_library_change(&event) => {
+                log::debug!("library change: {:?} {:?}", event.kind, event.paths);
+                let _ = tx.send(());
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("music directory watcher error: {}", e),
+        })
+        .inspect_err(|e| log::error!("failed to create music directory watcher: {}", e))
+        .ok()?;
+    for dir in dirs {
+        match watcher.watch(dir, RecursiveMode::Recursive) {
+            Ok(_) => log::info!("watching music directory {:?}", dir),
+            Err(e) => log::warn!("can't watch music directory {:?}: {}", dir, e),
+        }
+    }
+    // 发送端随 watcher 一起销毁后线程退出
+    thread::spawn(move || {
+        while rx.recv().is_ok() {
+            while rx.recv_timeout(WATCH_DEBOUNCE).is_ok() {}
+            on_change();
+        }
+    });
+    Some(watcher)
+}
+
+/// Whether `event` adds or removes songs, edits of file content are left to manual refreshes
+fn is_library_change(event: &Event) -> bool {
+    match event.kind {
+        EventKind::Create(CreateKind::Folder) | EventKind::Remove(RemoveKind::Folder) => true,
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_)) => {
+            event.paths.iter().any(utils::is_audio_file)
+        }
+        _ => false,
+    }
+}